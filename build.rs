@@ -6,16 +6,21 @@ use std::{
 };
 
 pub fn main() {
-    // Put the linker script somewhere the linker can find it
+    // Put the selected MCU's linker script somewhere the linker can find it
+    let memory: &[u8] = if env::var_os("CARGO_FEATURE_STM32F405").is_some() {
+        include_bytes!("memory/stm32f405.x")
+    } else {
+        include_bytes!("memory/stm32f411.x")
+    };
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("memory.x"))
+        .write_all(memory)
         .unwrap();
     println!("cargo:rustc-link-search={}", out.display());
 
     println!("cargo:rerun-if-changed=build.rs");
-    println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=memory");
     println!("cargo:rerun-if-changed=index.html");
 
     // Generate compressed site
@@ -0,0 +1,6 @@
+MEMORY
+{
+  /* The last 128K sector holds persistent configuration, see src/flash.rs */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 896K
+  RAM : ORIGIN = 0x20000000, LENGTH = 128K
+}
@@ -0,0 +1,203 @@
+//! A DRV8301 behind mock embedded-hal SPI and pins, answering frames as the datasheet describes
+
+use {
+    embedded_hal::{
+        blocking::{delay::DelayUs, spi::Transfer},
+        digital::{InputPin, OutputPin},
+    },
+    std::{cell::RefCell, rc::Rc},
+};
+
+const READ: u16 = 1 << 15;
+const FRAME_FAULT: u16 = 1 << 15;
+const DATA: u16 = 0x07ff;
+const CONTROL1: usize = 0x2;
+const GATE_RESET: u16 = 1 << 2;
+
+/// Handle to the model, cheap to clone
+#[derive(Clone)]
+pub struct MockDrv8301 {
+    chip: Rc<RefCell<Chip>>,
+}
+
+struct Chip {
+    registers: [u16; 4],
+    /// Answer to the next frame, as the reply to each frame arrives during the one after
+    reply: u16,
+    /// Every frame received, in order
+    frames: Vec<u16>,
+    /// Pin levels, high when true
+    cs: bool,
+    en_gate: bool,
+    nfault: bool,
+    noctw: bool,
+    /// Drop writes to the control registers
+    ignore_writes: bool,
+    /// Set the frame fault bit in every reply
+    garbled: bool,
+}
+
+impl MockDrv8301 {
+    /// Powered down, with every register clear and no fault
+    pub fn new() -> Self {
+        Self {
+            chip: Rc::new(RefCell::new(Chip {
+                registers: [0; 4],
+                reply: 0,
+                frames: Vec::new(),
+                cs: true,
+                en_gate: false,
+                nfault: true,
+                noctw: true,
+                ignore_writes: false,
+                garbled: false,
+            })),
+        }
+    }
+
+    pub fn spi(&self) -> MockSpi {
+        MockSpi {
+            chip: self.chip.clone(),
+        }
+    }
+
+    pub fn cs(&self) -> MockPin {
+        self.pin(|chip| &mut chip.cs)
+    }
+
+    pub fn en_gate(&self) -> MockPin {
+        self.pin(|chip| &mut chip.en_gate)
+    }
+
+    pub fn nfault(&self) -> MockPin {
+        self.pin(|chip| &mut chip.nfault)
+    }
+
+    pub fn noctw(&self) -> MockPin {
+        self.pin(|chip| &mut chip.noctw)
+    }
+
+    /// Frames received since the last call
+    pub fn take_frames(&self) -> Vec<u16> {
+        self.chip.borrow_mut().frames.drain(..).collect()
+    }
+
+    pub fn register(&self, address: usize) -> u16 {
+        self.chip.borrow().registers[address]
+    }
+
+    /// Latch faults in the status registers, pulling nFAULT low if any is set
+    pub fn set_status(&self, status1: u16, status2: u16) {
+        let mut chip = self.chip.borrow_mut();
+        chip.registers[0] = status1 & DATA;
+        chip.registers[1] = status2 & DATA;
+        chip.nfault = status1 == 0 && status2 == 0;
+    }
+
+    pub fn set_ignore_writes(&self, ignore: bool) {
+        self.chip.borrow_mut().ignore_writes = ignore;
+    }
+
+    pub fn set_garbled(&self, garbled: bool) {
+        self.chip.borrow_mut().garbled = garbled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.chip.borrow().en_gate
+    }
+
+    fn pin(&self, level: fn(&mut Chip) -> &mut bool) -> MockPin {
+        MockPin {
+            chip: self.chip.clone(),
+            level,
+        }
+    }
+}
+
+impl Default for MockDrv8301 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Chip {
+    fn frame(&mut self, frame: u16) -> u16 {
+        assert!(!self.cs, "frame {:#06x} sent without chip select", frame);
+        self.frames.push(frame);
+
+        let address = usize::from(frame >> 11 & 0xf);
+        let reply = self.reply;
+        self.reply = if frame & READ != 0 {
+            (frame & !DATA & !READ) | self.registers.get(address).cloned().unwrap_or(0)
+        } else {
+            // Writes answer with status register 1
+            if address >= CONTROL1 && address < self.registers.len() && !self.ignore_writes {
+                let mut data = frame & DATA;
+                if address == CONTROL1 && data & GATE_RESET != 0 {
+                    data &= !GATE_RESET;
+                    self.registers[0] = 0;
+                    self.registers[1] = 0;
+                    self.nfault = true;
+                }
+                self.registers[address] = data;
+            }
+            self.registers[0]
+        };
+
+        if self.garbled {
+            reply | FRAME_FAULT
+        } else {
+            reply
+        }
+    }
+}
+
+/// SPI bus with 16-bit words and only the DRV8301 on it
+pub struct MockSpi {
+    chip: Rc<RefCell<Chip>>,
+}
+
+impl Transfer<u16> for MockSpi {
+    type Error = ();
+
+    fn transfer<'w>(&mut self, words: &'w mut [u16]) -> Result<&'w [u16], ()> {
+        let mut chip = self.chip.borrow_mut();
+        for word in words.iter_mut() {
+            *word = chip.frame(*word);
+        }
+        Ok(words)
+    }
+}
+
+/// One of the DRV8301's pins, driven by whichever side it is an output of
+pub struct MockPin {
+    chip: Rc<RefCell<Chip>>,
+    level: fn(&mut Chip) -> &mut bool,
+}
+
+impl OutputPin for MockPin {
+    fn set_low(&mut self) {
+        *(self.level)(&mut self.chip.borrow_mut()) = false;
+    }
+
+    fn set_high(&mut self) {
+        *(self.level)(&mut self.chip.borrow_mut()) = true;
+    }
+}
+
+impl InputPin for MockPin {
+    fn is_high(&self) -> bool {
+        *(self.level)(&mut self.chip.borrow_mut())
+    }
+
+    fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
+
+/// Delays that return straight away, as nothing here needs time to pass
+pub struct NoDelay;
+
+impl DelayUs<u16> for NoDelay {
+    fn delay_us(&mut self, _us: u16) {}
+}
@@ -0,0 +1,203 @@
+//! Simulated motor and inverter for running the control code on the host
+//!
+//! `Simulator` owns a model of a BLDC motor behind a three-phase inverter. The phases it hands out
+//! implement `PhaseDriver` and its hall sensors `InputPin`, so `MotorDriver` and `HallSensor` drive
+//! it exactly as they drive the board. Time only passes when `step` is called, so tests decide how
+//! the interrupt handlers and tasks of the firmware interleave.
+
+#![allow(deprecated)]
+
+mod drv8301;
+mod plant;
+
+pub use crate::{
+    drv8301::{MockDrv8301, MockPin, MockSpi, NoDelay},
+    plant::{Gate, Motor},
+};
+
+use {
+    crate::plant::Plant,
+    crankshaft::{
+        hall::{HallSensor, HallTable},
+        motor::{MotorDriver, PhaseDriver},
+        Samples,
+    },
+    embedded_hal::digital::InputPin,
+    std::{cell::RefCell, f32::consts::PI, rc::Rc},
+};
+
+pub type Driver = MotorDriver<SimPhase, SimPhase, SimPhase>;
+pub type Hall = HallSensor<HallPin, HallPin, HallPin>;
+
+/// Handle to the model, cheap to clone
+#[derive(Clone)]
+pub struct Simulator {
+    plant: Rc<RefCell<Plant>>,
+}
+
+impl Simulator {
+    /// A stationary motor at zero electrical angle, with every phase floating
+    pub fn new(motor: Motor, bus_voltage: f32) -> Self {
+        Self {
+            plant: Rc::new(RefCell::new(Plant::new(motor, bus_voltage))),
+        }
+    }
+
+    /// Motor driver switching the simulated inverter
+    pub fn driver(&self) -> Driver {
+        MotorDriver::new(self.phase(0), self.phase(1), self.phase(2))
+    }
+
+    /// Hall sensors reading the simulated rotor, decoded with `table`
+    pub fn hall(&self, table: HallTable) -> Hall {
+        HallSensor::new(self.hall_pin(0), self.hall_pin(1), self.hall_pin(2), table)
+    }
+
+    /// Advance the model by `dt` seconds
+    pub fn step(&self, dt: f32) {
+        self.plant.borrow_mut().step(dt);
+    }
+
+    /// Current and bus voltage as the firmware's ADC reports them, with phase C inferred
+    pub fn samples(&self) -> Samples {
+        let plant = self.plant.borrow();
+        let [a, b, _] = plant.current;
+        Samples {
+            phase_current: [a, b, -(a + b)],
+            bus_voltage: plant.bus_voltage,
+        }
+    }
+
+    /// Phase terminal voltages, in volts, as sampled by the firmware during the high side on-time
+    pub fn phase_voltage(&self) -> [f32; 3] {
+        self.plant.borrow().terminal_voltage()
+    }
+
+    pub fn bus_voltage(&self) -> f32 {
+        self.plant.borrow().bus_voltage
+    }
+
+    pub fn set_bus_voltage(&self, volts: f32) {
+        self.plant.borrow_mut().bus_voltage = volts;
+    }
+
+    /// Speed in electrical RPM, positive in the firmware's forward direction
+    pub fn erpm(&self) -> f32 {
+        // Forward is `CommutationState::previous` order, which turns the field backwards
+        -self.plant.borrow().electrical_speed() * 60.0 / (2.0 * PI)
+    }
+
+    /// Electrical angle of the rotor, in radians, with phase A along zero
+    pub fn angle(&self) -> f32 {
+        self.plant.borrow().angle
+    }
+
+    pub fn set_angle(&self, angle: f32) {
+        self.plant.borrow_mut().angle = angle;
+    }
+
+    /// Torque produced by the motor, in newton metres, positive in the firmware's forward
+    /// direction
+    pub fn torque(&self) -> f32 {
+        -self.plant.borrow().torque()
+    }
+
+    /// How each phase is being switched
+    pub fn gates(&self) -> [Gate; 3] {
+        self.plant.borrow().gates
+    }
+
+    /// Duty cycle each phase is switched at
+    pub fn duties(&self) -> [f32; 3] {
+        self.plant.borrow().duties
+    }
+
+    /// Torque opposing the rotor whichever way it turns, in newton metres
+    pub fn set_load(&self, torque: f32) {
+        self.plant.borrow_mut().load = torque;
+    }
+
+    /// Hold the rotor still, as if it were jammed
+    pub fn set_locked(&self, locked: bool) {
+        self.plant.borrow_mut().locked = locked;
+    }
+
+    /// How the hall sensors are wired, given as the table that decodes them correctly
+    pub fn set_hall_wiring(&self, wiring: HallTable) {
+        self.plant.borrow_mut().wiring = wiring;
+    }
+
+    /// Force one hall sensor, numbered 0 to 2, high or low as by a broken wire, or release it
+    pub fn set_hall_stuck(&self, sensor: usize, level: Option<bool>) {
+        self.plant.borrow_mut().stuck[sensor] = level;
+    }
+
+    /// Raw hall sensor output, with sensor 1 as the least significant bit
+    pub fn hall_code(&self) -> u8 {
+        self.plant.borrow().hall_code()
+    }
+
+    fn phase(&self, phase: usize) -> SimPhase {
+        SimPhase {
+            plant: self.plant.clone(),
+            phase,
+        }
+    }
+
+    fn hall_pin(&self, sensor: usize) -> HallPin {
+        HallPin {
+            plant: self.plant.clone(),
+            sensor,
+        }
+    }
+}
+
+/// One half-bridge of the simulated inverter
+pub struct SimPhase {
+    plant: Rc<RefCell<Plant>>,
+    phase: usize,
+}
+
+impl SimPhase {
+    fn set_gate(&mut self, gate: Gate) {
+        self.plant.borrow_mut().gates[self.phase] = gate;
+    }
+}
+
+impl PhaseDriver for SimPhase {
+    fn set_floating(&mut self) {
+        self.set_gate(Gate::Floating);
+    }
+
+    fn set_high(&mut self) {
+        self.set_gate(Gate::High);
+    }
+
+    fn set_low(&mut self) {
+        self.set_gate(Gate::Low);
+    }
+
+    fn set_duty(&mut self, duty: f32) {
+        self.plant.borrow_mut().duties[self.phase] = duty;
+    }
+
+    fn set_low_chopped(&mut self) {
+        self.set_gate(Gate::LowChopped);
+    }
+}
+
+/// One simulated hall sensor output
+pub struct HallPin {
+    plant: Rc<RefCell<Plant>>,
+    sensor: usize,
+}
+
+impl InputPin for HallPin {
+    fn is_high(&self) -> bool {
+        self.plant.borrow().hall_code() & 1 << self.sensor != 0
+    }
+
+    fn is_low(&self) -> bool {
+        !self.is_high()
+    }
+}
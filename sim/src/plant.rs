@@ -0,0 +1,305 @@
+//! Star-connected BLDC motor behind a three-phase inverter, averaged over each PWM period
+
+use {
+    crankshaft::{hall::HallTable, motor::CommutationState},
+    std::f32::consts::PI,
+};
+
+/// Longest time the electrical model is integrated over in one go, in seconds
+const MAX_STEP: f32 = 5e-6;
+/// Electrical angle of each phase's winding axis
+const PHASE_ANGLE: [f32; 3] = [0.0, 2.0 * PI / 3.0, -2.0 * PI / 3.0];
+const STATES: [CommutationState; 6] = [
+    CommutationState::AB,
+    CommutationState::AC,
+    CommutationState::BC,
+    CommutationState::BA,
+    CommutationState::CA,
+    CommutationState::CB,
+];
+
+/// Motor parameters, per phase of the star
+#[derive(Debug, Clone, Copy)]
+pub struct Motor {
+    /// Phase resistance, in ohms
+    pub resistance: f32,
+    /// Phase inductance, in henries
+    pub inductance: f32,
+    /// Peak flux linkage of each phase with the rotor magnets, in webers
+    pub flux_linkage: f32,
+    pub pole_pairs: u32,
+    /// Rotor and load inertia, in kg m²
+    pub inertia: f32,
+    /// Viscous friction, in newton metres per radian per second
+    pub friction: f32,
+}
+
+impl Default for Motor {
+    /// A small 7 pole pair outrunner, like the one `config::MOTOR` is tuned for
+    fn default() -> Self {
+        Self {
+            resistance: 0.1,
+            inductance: 50e-6,
+            flux_linkage: 0.005,
+            pole_pairs: 7,
+            inertia: 1e-4,
+            friction: 1e-5,
+        }
+    }
+}
+
+/// How the two switches of a half-bridge are driven
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gate {
+    /// Both off, so only the body diodes conduct
+    Floating,
+    /// Complementary switching at the phase's duty cycle
+    High,
+    /// Low side on
+    Low,
+    /// Low side switched at the phase's duty cycle, high side off
+    LowChopped,
+}
+
+pub struct Plant {
+    pub motor: Motor,
+    pub bus_voltage: f32,
+    /// Torque opposing the rotor whichever way it turns, in newton metres
+    pub load: f32,
+    /// Holds the rotor still, for stall tests
+    pub locked: bool,
+    pub gates: [Gate; 3],
+    pub duties: [f32; 3],
+    /// Amps flowing into the motor
+    pub current: [f32; 3],
+    /// Electrical angle of the rotor flux, in radians, with phase A along zero
+    pub angle: f32,
+    /// Mechanical speed, in radians per second, positive with increasing `angle`
+    pub speed: f32,
+    /// Maps each hall code to the commutation state the rotor is aligned with when it is output
+    pub wiring: HallTable,
+    /// Hall sensors forced high or low, as by a broken wire
+    pub stuck: [Option<bool>; 3],
+}
+
+impl Plant {
+    pub fn new(motor: Motor, bus_voltage: f32) -> Self {
+        Self {
+            motor,
+            bus_voltage,
+            load: 0.0,
+            locked: false,
+            gates: [Gate::Floating; 3],
+            duties: [0.0; 3],
+            current: [0.0; 3],
+            angle: 0.0,
+            speed: 0.0,
+            wiring: HallTable::default(),
+            stuck: [None; 3],
+        }
+    }
+
+    /// Advance by `dt` seconds with the gates as they are
+    pub fn step(&mut self, dt: f32) {
+        let steps = (dt / MAX_STEP).ceil().max(1.0);
+        for _ in 0..steps as u32 {
+            self.integrate(dt / steps);
+        }
+    }
+
+    /// Electrical speed, in radians per second
+    pub fn electrical_speed(&self) -> f32 {
+        self.speed * self.motor.pole_pairs as f32
+    }
+
+    /// Back-EMF of each phase, in volts
+    pub fn emf(&self) -> [f32; 3] {
+        let mut emf = [0.0; 3];
+        for (e, offset) in emf.iter_mut().zip(PHASE_ANGLE.iter()) {
+            *e = -self.electrical_speed() * self.motor.flux_linkage * (self.angle - offset).sin();
+        }
+        emf
+    }
+
+    /// Torque on the rotor from the phase currents, in newton metres
+    pub fn torque(&self) -> f32 {
+        let mut torque = 0.0;
+        for (i, offset) in self.current.iter().zip(PHASE_ANGLE.iter()) {
+            torque -= self.motor.flux_linkage * (self.angle - offset).sin() * i;
+        }
+        torque * self.motor.pole_pairs as f32
+    }
+
+    /// Voltage on each phase terminal with every high side that is switching turned on, which is
+    /// when the firmware samples them
+    pub fn terminal_voltage(&self) -> [f32; 3] {
+        let bus = self.bus_voltage;
+        let (_, _, terminal) = self.solve(|x, gate| match gate {
+            Gate::High => Some(bus),
+            Gate::Low => Some(0.0),
+            Gate::LowChopped | Gate::Floating => diode(self.current[x], bus),
+        });
+        terminal
+    }
+
+    /// Output of the three hall sensors, with sensor 1 as the least significant bit
+    pub fn hall_code(&self) -> u8 {
+        let angle = wrap(self.angle);
+        let aligned = STATES.iter().find(|state| {
+            let offset = wrap(angle - state.angle());
+            (-PI / 6.0..PI / 6.0).contains(&offset)
+        });
+
+        let mut code = aligned
+            .and_then(|&state| {
+                (0..8u8).find(|&code| self.wiring.0[usize::from(code)] == Some(state))
+            })
+            .unwrap_or(0);
+        for (sensor, stuck) in self.stuck.iter().enumerate() {
+            match stuck {
+                Some(true) => code |= 1 << sensor,
+                Some(false) => code &= !(1 << sensor),
+                None => (),
+            }
+        }
+        code
+    }
+
+    fn integrate(&mut self, dt: f32) {
+        let bus = self.bus_voltage;
+        let current = self.current;
+        let (connected, neutral, _) = self.solve(|x, gate| match gate {
+            Gate::High => Some(self.duties[x] * bus),
+            Gate::Low => Some(0.0),
+            Gate::LowChopped if current[x] < 0.0 => Some((1.0 - self.duties[x]) * bus),
+            Gate::LowChopped => Some(0.0),
+            Gate::Floating => diode(current[x], bus),
+        });
+
+        let emf = self.emf();
+        let motor = self.motor;
+        let mut carrying = 0;
+        for x in 0..3 {
+            let previous = self.current[x];
+            self.current[x] = match connected[x] {
+                Some(v) => {
+                    previous
+                        + (v - neutral - motor.resistance * previous - emf[x]) / motor.inductance
+                            * dt
+                }
+                None => 0.0,
+            };
+
+            // A body diode stops conducting when its current reaches zero
+            if self.gates[x] == Gate::Floating && previous * self.current[x] < 0.0 {
+                self.current[x] = 0.0;
+            }
+            if self.current[x] != 0.0 {
+                carrying += 1;
+            }
+        }
+
+        // Currents into a star sum to zero, whatever rounding or a diode turning off did
+        let residual: f32 = self.current.iter().sum();
+        if carrying > 0 {
+            for i in self.current.iter_mut().filter(|i| **i != 0.0) {
+                *i -= residual / carrying as f32;
+            }
+        }
+
+        if self.locked {
+            self.speed = 0.0;
+        } else {
+            let drive = self.torque() - motor.friction * self.speed;
+            let previous = self.speed;
+            if previous == 0.0 && drive.abs() <= self.load {
+                // Held by the load
+            } else {
+                let load = if previous > 0.0 || (previous == 0.0 && drive > 0.0) {
+                    self.load
+                } else {
+                    -self.load
+                };
+                self.speed += (drive - load) / motor.inertia * dt;
+                // Load stops the rotor rather than reversing it
+                if previous != 0.0 && previous * self.speed < 0.0 {
+                    self.speed = 0.0;
+                }
+            }
+        }
+        self.angle = wrap(self.angle + self.electrical_speed() * dt);
+    }
+
+    /// Neutral point voltage for the given driven terminal voltages
+    ///
+    /// Open phases carry no current, so their terminals sit at the neutral plus their back-EMF.
+    /// If that is beyond a rail, the body diode to it conducts and the phase is driven after all.
+    /// Returns the phases that conduct, the neutral and every terminal voltage.
+    fn solve<F>(&self, drive: F) -> ([Option<f32>; 3], f32, [f32; 3])
+    where
+        F: Fn(usize, Gate) -> Option<f32>,
+    {
+        let emf = self.emf();
+        let bus = self.bus_voltage;
+        let mut connected = [None; 3];
+        for (x, v) in connected.iter_mut().enumerate() {
+            *v = drive(x, self.gates[x]);
+        }
+
+        let mut neutral = bus / 2.0;
+        for _ in 0..3 {
+            let driven: Vec<f32> = (0..3)
+                .filter_map(|x| connected[x].map(|v| v - emf[x]))
+                .collect();
+            neutral = match driven.len() {
+                0 => bus / 2.0,
+                n => driven.iter().sum::<f32>() / n as f32,
+            };
+
+            let mut changed = false;
+            for x in 0..3 {
+                if connected[x].is_none() {
+                    let v = neutral + emf[x];
+                    if v > bus {
+                        connected[x] = Some(bus);
+                        changed = true;
+                    } else if v < 0.0 {
+                        connected[x] = Some(0.0);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut terminal = [0.0; 3];
+        for x in 0..3 {
+            terminal[x] = connected[x].unwrap_or(neutral + emf[x]);
+        }
+        (connected, neutral, terminal)
+    }
+}
+
+/// Voltage a floating phase is clamped to by the body diode carrying its current
+fn diode(current: f32, bus: f32) -> Option<f32> {
+    if current > 0.0 {
+        Some(0.0)
+    } else if current < 0.0 {
+        Some(bus)
+    } else {
+        None
+    }
+}
+
+/// Angle in radians, from -π to π
+fn wrap(angle: f32) -> f32 {
+    let mut angle = angle % (2.0 * PI);
+    if angle >= PI {
+        angle -= 2.0 * PI;
+    } else if angle < -PI {
+        angle += 2.0 * PI;
+    }
+    angle
+}
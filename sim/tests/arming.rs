@@ -0,0 +1,177 @@
+use crankshaft::{
+    arming::{Arming, Rejection},
+    config,
+    fault::Fault,
+    motor::{BrakeMode, ControlState},
+};
+
+const BUS_VOLTAGE: f32 = 24.0;
+
+fn armed() -> Arming {
+    let mut arming = Arming::new();
+    arming.arm(ControlState::Idle, None, BUS_VOLTAGE).unwrap();
+    arming
+}
+
+#[test]
+fn only_idle_until_armed() {
+    let arming = Arming::new();
+    assert!(!arming.is_armed());
+
+    assert!(arming
+        .check(ControlState::Idle, ControlState::Idle, None, 0.0)
+        .is_ok());
+    for &to in [
+        ControlState::Forward,
+        ControlState::Duty(0.1),
+        ControlState::Current(0.0),
+        ControlState::Brake(BrakeMode::Short),
+        ControlState::DetectHall,
+    ]
+    .iter()
+    {
+        match arming.check(ControlState::Idle, to, None, 0.0) {
+            Err(Rejection::Disarmed) => {}
+            result => panic!("{:?} gave {:?}", to, result),
+        }
+    }
+}
+
+#[test]
+fn arming_needs_no_fault() {
+    let mut arming = Arming::new();
+    match arming.arm(ControlState::Idle, Some(Fault::OverCurrent), BUS_VOLTAGE) {
+        Err(Rejection::Fault(Fault::OverCurrent)) => {}
+        result => panic!("{:?}", result),
+    }
+    assert!(!arming.is_armed());
+}
+
+#[test]
+fn arming_needs_the_bus_voltage_in_range() {
+    let mut arming = Arming::new();
+    for &volts in [
+        0.0,
+        config::LIMITS.min_voltage - 0.1,
+        config::LIMITS.max_voltage + 0.1,
+    ]
+    .iter()
+    {
+        match arming.arm(ControlState::Idle, None, volts) {
+            Err(Rejection::BusVoltage(v)) => assert_eq!(v, volts),
+            result => panic!("{}V gave {:?}", volts, result),
+        }
+        assert!(!arming.is_armed());
+    }
+}
+
+#[test]
+fn arming_needs_a_zero_setpoint() {
+    for &control in [
+        ControlState::Forward,
+        ControlState::Reverse,
+        ControlState::Duty(0.1),
+        ControlState::Speed(-100.0),
+        ControlState::Current(2.0),
+        ControlState::DetectHall,
+        ControlState::Identify,
+    ]
+    .iter()
+    {
+        let mut arming = Arming::new();
+        match arming.arm(control, None, BUS_VOLTAGE) {
+            Err(Rejection::NotZero) => {}
+            result => panic!("{:?} gave {:?}", control, result),
+        }
+        assert!(!arming.is_armed());
+    }
+
+    for &control in [
+        ControlState::Idle,
+        ControlState::Brake(BrakeMode::Coast),
+        ControlState::Duty(0.0),
+        ControlState::Speed(0.0),
+        ControlState::Current(0.0),
+    ]
+    .iter()
+    {
+        let mut arming = Arming::new();
+        assert!(
+            arming.arm(control, None, BUS_VOLTAGE).is_ok(),
+            "{:?}",
+            control
+        );
+        assert!(arming.is_armed());
+    }
+}
+
+#[test]
+fn fault_disarms() {
+    let mut arming = armed();
+    arming.update(None);
+    assert!(arming.is_armed());
+
+    arming.update(Some(Fault::OverTempFet));
+    assert!(!arming.is_armed());
+    match arming.check(
+        ControlState::Idle,
+        ControlState::Forward,
+        Some(Fault::OverTempFet),
+        0.0,
+    ) {
+        Err(Rejection::Fault(Fault::OverTempFet)) => {}
+        result => panic!("{:?}", result),
+    }
+
+    // Still disarmed once the fault is cleared
+    arming.update(None);
+    match arming.check(ControlState::Idle, ControlState::Forward, None, 0.0) {
+        Err(Rejection::Disarmed) => {}
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn no_reversing_at_speed() {
+    let arming = armed();
+    let fast = config::MOTOR.max_reversal_erpm + 100.0;
+
+    match arming.check(ControlState::Forward, ControlState::Reverse, None, fast) {
+        Err(Rejection::Reversal) => {}
+        result => panic!("{:?}", result),
+    }
+    assert!(arming
+        .check(ControlState::Forward, ControlState::Forward, None, fast)
+        .is_ok());
+    // Reverse current regenerates
+    assert!(arming
+        .check(
+            ControlState::Forward,
+            ControlState::Current(-5.0),
+            None,
+            fast
+        )
+        .is_ok());
+}
+
+#[test]
+fn detection_needs_the_rotor_at_rest() {
+    let arming = armed();
+
+    match arming.check(ControlState::Idle, ControlState::DetectHall, None, 10.0) {
+        Err(Rejection::Moving) => {}
+        result => panic!("{:?}", result),
+    }
+    assert!(arming
+        .check(ControlState::Idle, ControlState::Identify, None, 0.0)
+        .is_ok());
+    // Detection moves the rotor itself, which must not stop it
+    assert!(arming
+        .check(
+            ControlState::DetectHall,
+            ControlState::DetectHall,
+            None,
+            10.0
+        )
+        .is_ok());
+}
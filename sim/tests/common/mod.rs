@@ -0,0 +1,106 @@
+//! Firmware timing around the simulator, with the interrupt handlers reduced to what each test
+//! needs
+
+#![allow(dead_code)]
+
+use {
+    crankshaft::{
+        config,
+        fault::{Fault, Faults},
+        hall::HallTable,
+        motor::CommutationState,
+        speed::Tachometer,
+    },
+    crankshaft_sim::{Driver, Hall, Motor, Simulator},
+};
+
+pub const PWM_HZ: u32 = 20_000;
+pub const MOTOR_TASK_HZ: u32 = 128;
+pub const BUS_VOLTAGE: f32 = 24.0;
+/// PWM periods between runs of `motor_task`
+pub const TASK_PERIODS: u32 = PWM_HZ / MOTOR_TASK_HZ;
+
+pub struct Rig {
+    pub sim: Simulator,
+    pub driver: Driver,
+    pub hall: Hall,
+    pub tachometer: Tachometer,
+    pub faults: Faults,
+    /// PWM periods since the start
+    pub periods: u32,
+    code: u8,
+}
+
+impl Rig {
+    pub fn new() -> Self {
+        Self::with_motor(Motor::default())
+    }
+
+    pub fn with_motor(motor: Motor) -> Self {
+        let sim = Simulator::new(motor, BUS_VOLTAGE);
+        let driver = sim.driver();
+        let hall = sim.hall(HallTable::default());
+        let code = sim.hall_code();
+
+        Self {
+            sim,
+            driver,
+            hall,
+            tachometer: Tachometer::new(PWM_HZ),
+            faults: Faults::new(config::LIMITS),
+            periods: 0,
+            code,
+        }
+    }
+
+    /// One PWM period, as `TIM1_UP_TIM10` and `ADC` see it
+    ///
+    /// Checks the samples for faults and floats the phases on one, as `ADC` does. Returns the
+    /// rotor position when the hall code changed, after feeding it to the tachometer as `EXTI9_5`
+    /// does.
+    pub fn period(&mut self) -> Option<CommutationState> {
+        self.sim.step(1.0 / PWM_HZ as f32);
+        self.periods += 1;
+        self.tachometer.tick();
+
+        if self.faults.check(&self.sim.samples()).is_some() {
+            self.driver.set_idle();
+        }
+
+        let code = self.sim.hall_code();
+        if code == self.code {
+            return None;
+        }
+        self.code = code;
+
+        let position = self.hall.position();
+        if let Some(position) = position {
+            self.tachometer.edge(position);
+        }
+        position
+    }
+
+    /// Whether `motor_task` is due after the latest period
+    pub fn task_due(&self) -> bool {
+        self.periods % TASK_PERIODS == 0
+    }
+
+    /// Six-step hall commutation, as `EXTI9_5` and `motor_task` do it
+    pub fn commutate_hall(&mut self, direction: bool) {
+        if self.faults.active().is_some() {
+            return;
+        }
+        if let Some(state) = self.hall.commutation(direction) {
+            self.driver.commutate(state);
+        }
+    }
+
+    pub fn fault(&self) -> Option<Fault> {
+        self.faults.active()
+    }
+}
+
+/// PWM periods in `seconds`
+pub fn periods(seconds: f32) -> u32 {
+    (seconds * PWM_HZ as f32) as u32
+}
@@ -0,0 +1,60 @@
+use {
+    core::fmt::Write,
+    crankshaft::{
+        crash::Record,
+        http::{route, Method, Route, Routed},
+    },
+};
+
+const ROUTES: &[Route<()>] = &[Route {
+    method: Method::Get,
+    path: "/panic",
+    handler: (),
+}];
+
+fn panicked(message: &str) -> Record {
+    let mut record = Record::new();
+    record.begin();
+    write!(record, "{}", message).unwrap();
+    record.finish();
+    record
+}
+
+#[test]
+fn head_request_leaves_the_record_for_get() {
+    let mut record = panicked("panicked at 'oops', src/main.rs:1:1");
+
+    // As the firmware answers, leaving out the body of a `HEAD` response
+    let mut request = |method: &str| {
+        assert_eq!(route(ROUTES, method, "/panic"), Routed::Found(()));
+        record
+            .report(method == "HEAD")
+            .map(|r| r.message().to_string())
+    };
+
+    let message = Some("panicked at 'oops', src/main.rs:1:1".to_string());
+    assert_eq!(request("HEAD"), message);
+    assert_eq!(request("HEAD"), message);
+    assert_eq!(request("GET"), message);
+    assert_eq!(request("GET"), None);
+}
+
+#[test]
+fn only_finished_records_are_reported() {
+    assert!(Record::new().report(false).is_none());
+
+    let mut record = panicked("first");
+    record.begin();
+    write!(record, "second, interrupted").unwrap();
+    assert!(record.report(false).is_none());
+}
+
+#[test]
+fn truncates_on_a_character_boundary() {
+    let long = "é".repeat(100);
+    let mut record = panicked(&long);
+    let message = record.report(false).unwrap().message().to_string();
+
+    assert!(message.len() <= 128 && message.len() >= 127);
+    assert!(long.starts_with(&message));
+}
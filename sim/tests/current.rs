@@ -0,0 +1,91 @@
+use crankshaft::{current::CurrentLoop, motor::CommutationState, pid::Pid, Samples};
+
+const DT: f32 = 1.0 / 20_000.0;
+
+fn current_loop() -> CurrentLoop {
+    CurrentLoop::new(Pid::new(0.01, 10.0, 0.0, 1.0, 0.0, 1.0))
+}
+
+/// Samples with `amps` flowing in through the phase `state` drives high
+fn measured(state: CommutationState, amps: f32) -> Samples {
+    let mut samples = Samples::new();
+    let (high, low, _) = state.phases();
+    samples.phase_current[high] = amps;
+    samples.phase_current[low] = -amps;
+    samples
+}
+
+#[test]
+fn stopped_until_set() {
+    let mut current = current_loop();
+    let samples = measured(CommutationState::AB, 0.0);
+    assert_eq!(current.update(&samples, CommutationState::AB, DT), None);
+    assert_eq!(current.direction(), None);
+
+    current.set(true, 5.0);
+    assert_eq!(current.direction(), Some(true));
+    assert!(current.update(&samples, CommutationState::AB, DT).is_some());
+
+    current.stop();
+    assert_eq!(current.update(&samples, CommutationState::AB, DT), None);
+}
+
+#[test]
+fn duty_is_limited() {
+    let mut current = current_loop();
+    let state = CommutationState::BC;
+
+    current.set(false, 1_000.0);
+    let duty = current.update(&measured(state, 0.0), state, DT);
+    assert_eq!(duty, Some(1.0));
+
+    // Regenerating hard can ask for less than nothing
+    current.set(false, -1_000.0);
+    for _ in 0..1_000 {
+        let duty = current.update(&measured(state, 0.0), state, DT).unwrap();
+        assert!((0.0..=1.0).contains(&duty), "{}", duty);
+    }
+    assert_eq!(current.update(&measured(state, 0.0), state, DT), Some(0.0));
+}
+
+#[test]
+fn integrator_does_not_wind_up() {
+    let mut current = current_loop();
+    let state = CommutationState::CA;
+
+    // A second saturated, as with the rotor stalled against the current limit
+    current.set(false, 100.0);
+    for _ in 0..20_000 {
+        assert_eq!(current.update(&measured(state, 0.0), state, DT), Some(1.0));
+    }
+
+    // Backs off straight away once the target is exceeded, rather than unwinding for seconds
+    current.set(false, 5.0);
+    let duty = current.update(&measured(state, 20.0), state, DT).unwrap();
+    assert!(duty < 1.0, "{}", duty);
+    let mut periods = 0;
+    while current.update(&measured(state, 20.0), state, DT).unwrap() > 0.0 {
+        periods += 1;
+        assert!(periods < 20_000, "still driving after a second");
+    }
+}
+
+#[test]
+fn reversing_resets_the_integrator() {
+    let mut current = current_loop();
+    let state = CommutationState::AB;
+
+    current.set(false, 10.0);
+    for _ in 0..1_000 {
+        current.update(&measured(state, 0.0), state, DT);
+    }
+
+    // Only the proportional term and one period of integral, none of what built up before
+    current.set(true, 10.0);
+    let duty = current.update(&measured(state, 0.0), state, DT).unwrap();
+    assert!(
+        (duty - (0.01 * 10.0 + 10.0 * 10.0 * DT)).abs() < 1e-5,
+        "{}",
+        duty
+    );
+}
@@ -0,0 +1,34 @@
+use crankshaft::{
+    config,
+    deadman::{Deadman, Source},
+    motor::ControlState,
+};
+
+#[test]
+fn falls_back_once_source_goes_quiet() {
+    let mut deadman = Deadman::new(config::COMMAND_TIMEOUT, config::THROTTLE_TIMEOUT);
+    assert!(deadman.update(5_000).is_none());
+
+    deadman.refresh(Source::Throttle, 10_000);
+    assert_eq!(deadman.remaining(10_250), Some(0.25));
+    assert!(deadman.update(10_499).is_none());
+
+    // Heard from again, so the timeout starts over
+    deadman.refresh(Source::Throttle, 10_499);
+    assert!(deadman.update(10_998).is_none());
+    assert!(matches!(deadman.update(10_999), Some(ControlState::Idle)));
+
+    // Only falls back once
+    assert!(deadman.update(20_000).is_none());
+    assert_eq!(deadman.remaining(20_000), None);
+}
+
+#[test]
+fn tolerates_refresh_stamped_after_check() {
+    let mut deadman = Deadman::new(config::COMMAND_TIMEOUT, config::THROTTLE_TIMEOUT);
+
+    // The network loop can read the clock after the motor task, then refresh before it checks
+    deadman.refresh(Source::Command, 1_001);
+    assert!(deadman.update(1_000).is_none());
+    assert_eq!(deadman.remaining(1_000), Some(1.0));
+}
@@ -0,0 +1,138 @@
+use {
+    crankshaft::{
+        config,
+        gate::{DriverFault, Drv8301, Fet, GateDriver},
+    },
+    crankshaft_sim::{MockDrv8301, MockPin, MockSpi, NoDelay},
+};
+
+type Driver = Drv8301<MockSpi, MockPin, MockPin, MockPin, MockPin>;
+
+fn driver() -> (MockDrv8301, Driver) {
+    let chip = MockDrv8301::new();
+    let driver = Drv8301::new(
+        chip.spi(),
+        chip.cs(),
+        chip.en_gate(),
+        chip.nfault(),
+        chip.noctw(),
+        config::DRV8301,
+    );
+    (chip, driver)
+}
+
+/// Control registers as `config::DRV8301` should set them
+fn expected_control() -> (u16, u16) {
+    let config = config::DRV8301;
+    let control1 = config.gate_current as u16
+        | (config.pwm_mode as u16) << 3
+        | (config.ocp_mode as u16) << 4
+        | u16::from(config.oc_adj) << 6;
+    let control2 = (config.gain as u16) << 2;
+    (control1, control2)
+}
+
+#[test]
+fn write_is_one_frame() {
+    let (chip, mut driver) = driver();
+
+    driver.write(0x3, 0x0c).unwrap();
+    // Write bit clear, address in bits 14 to 11, data in the rest
+    assert_eq!(chip.take_frames(), vec![0x3 << 11 | 0x0c]);
+    assert_eq!(chip.register(0x3), 0x0c);
+
+    // Data past 11 bits is not allowed to spill into the address
+    driver.write(0x2, 0xffff).unwrap();
+    assert_eq!(chip.take_frames(), vec![0x2 << 11 | 0x07ff]);
+}
+
+#[test]
+fn read_takes_two_frames() {
+    let (chip, mut driver) = driver();
+    driver.write(0x2, 0x0123).unwrap();
+    chip.take_frames();
+
+    assert_eq!(driver.read(0x2), Ok(0x0123));
+    let frame = 1 << 15 | 0x2 << 11;
+    assert_eq!(chip.take_frames(), vec![frame, frame]);
+}
+
+#[test]
+fn garbled_reply_is_an_spi_fault() {
+    let (chip, mut driver) = driver();
+    chip.set_garbled(true);
+    assert_eq!(driver.read(0x2), Err(DriverFault::Spi));
+}
+
+#[test]
+fn enable_configures_and_checks() {
+    let (chip, mut driver) = driver();
+    assert!(!chip.is_enabled());
+
+    driver.enable(&mut NoDelay).unwrap();
+    assert!(chip.is_enabled());
+    let (control1, control2) = expected_control();
+    assert_eq!(chip.register(0x2), control1);
+    assert_eq!(chip.register(0x3), control2);
+}
+
+#[test]
+fn configuration_readback_mismatch() {
+    let (chip, mut driver) = driver();
+    chip.set_ignore_writes(true);
+
+    assert_eq!(driver.enable(&mut NoDelay), Err(DriverFault::Config));
+    // Left disabled rather than running unconfigured
+    assert!(!chip.is_enabled());
+}
+
+#[test]
+fn no_fault_while_nfault_is_high() {
+    let (chip, mut driver) = driver();
+    driver.enable(&mut NoDelay).unwrap();
+    chip.take_frames();
+
+    assert_eq!(driver.fault(), None);
+    // Nothing to ask the driver about
+    assert!(chip.take_frames().is_empty());
+}
+
+#[test]
+fn status_registers_decode_into_faults() {
+    let cases = [
+        (1 << 8, 0, DriverFault::PvddUnderVoltage),
+        (1 << 9, 0, DriverFault::GvddUnderVoltage),
+        (1 << 7, 0, DriverFault::OverTemperature),
+        (1 << 5, 0, DriverFault::OverCurrent(Fet::HighA)),
+        (1 << 4, 0, DriverFault::OverCurrent(Fet::LowA)),
+        (1 << 3, 0, DriverFault::OverCurrent(Fet::HighB)),
+        (1 << 2, 0, DriverFault::OverCurrent(Fet::LowB)),
+        (1 << 1, 0, DriverFault::OverCurrent(Fet::HighC)),
+        (1 << 0, 0, DriverFault::OverCurrent(Fet::LowC)),
+        // Undervoltage explains any overcurrent reported with it
+        (1 << 8 | 1 << 3, 0, DriverFault::PvddUnderVoltage),
+        (0, 1 << 7, DriverFault::GvddOverVoltage),
+        // nFAULT low without a reason
+        (0, 1 << 3, DriverFault::Unspecified),
+    ];
+
+    for &(status1, status2, fault) in cases.iter() {
+        let (chip, mut driver) = driver();
+        driver.enable(&mut NoDelay).unwrap();
+        chip.set_status(status1, status2);
+        assert_eq!(driver.fault(), Some(fault), "{:#x} {:#x}", status1, status2);
+    }
+}
+
+#[test]
+fn reset_clears_the_latched_fault() {
+    let (chip, mut driver) = driver();
+    driver.enable(&mut NoDelay).unwrap();
+    chip.set_status(1 << 5, 0);
+    assert_eq!(driver.fault(), Some(DriverFault::OverCurrent(Fet::HighA)));
+
+    driver.reset(&mut NoDelay);
+    assert_eq!(driver.fault(), None);
+    // The reset bit clears itself, the configuration stays
+    assert_eq!(chip.register(0x2), expected_control().0);
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use {
+    common::{periods, Rig},
+    crankshaft::fault::Fault,
+    crankshaft_sim::Gate,
+};
+
+#[test]
+fn stalled_rotor_trips_overcurrent() {
+    let mut rig = Rig::new();
+    rig.sim.set_locked(true);
+
+    rig.driver.set_duty(0.9);
+    rig.commutate_hall(false);
+    for _ in 0..periods(0.05) {
+        rig.period();
+    }
+
+    assert_eq!(rig.fault(), Some(Fault::OverCurrent));
+    assert_eq!(rig.sim.gates(), [Gate::Floating; 3]);
+    let current = rig.sim.samples().phase_current;
+    assert!(current.iter().all(|i| i.abs() < 0.1), "{:?}", current);
+}
+
+#[test]
+fn bus_sag_trips_undervoltage_after_filtering() {
+    let mut rig = Rig::new();
+    rig.sim.set_bus_voltage(6.0);
+
+    for _ in 0..10 {
+        rig.period();
+    }
+    assert_eq!(rig.fault(), None);
+
+    for _ in 0..10 {
+        rig.period();
+    }
+    assert_eq!(rig.fault(), Some(Fault::UnderVoltage));
+}
+
+#[test]
+fn fault_stays_latched_until_cleared() {
+    let mut rig = Rig::new();
+    rig.sim.set_bus_voltage(70.0);
+    for _ in 0..periods(0.01) {
+        rig.period();
+    }
+    assert_eq!(rig.fault(), Some(Fault::OverVoltage));
+
+    rig.sim.set_bus_voltage(24.0);
+    for _ in 0..periods(0.01) {
+        rig.period();
+    }
+    assert_eq!(rig.fault(), Some(Fault::OverVoltage));
+
+    rig.faults.clear();
+    rig.period();
+    assert_eq!(rig.fault(), None);
+}
@@ -0,0 +1,168 @@
+use {
+    crankshaft::{
+        foc::{clarke, inverse_park, park, sin_cos, svpwm, AlphaBeta, HallAngle},
+        motor::CommutationState,
+    },
+    std::f32::consts::{FRAC_PI_3, FRAC_PI_6, PI},
+};
+
+/// Angles over a full electrical rotation and a bit either side, to exercise wrapping
+fn angles() -> impl Iterator<Item = f32> {
+    (-400..=400).map(|i| i as f32 * 0.01)
+}
+
+fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+    assert!(
+        (actual - expected).abs() <= tolerance,
+        "{} is not within {} of {}",
+        actual,
+        tolerance,
+        expected
+    );
+}
+
+#[test]
+fn sin_cos_matches_std() {
+    for angle in angles().map(|angle| angle * 3.0) {
+        let (sin, cos) = sin_cos(angle);
+        assert_close(sin, angle.sin(), 0.002);
+        assert_close(cos, angle.cos(), 0.002);
+    }
+}
+
+#[test]
+fn park_round_trip() {
+    for angle in angles() {
+        let (sin, cos) = angle.sin_cos();
+        let v = clarke(0.7, -0.2);
+        let back = inverse_park(park(v, sin, cos), sin, cos);
+        assert_close(back.alpha, v.alpha, 1e-5);
+        assert_close(back.beta, v.beta, 1e-5);
+    }
+}
+
+#[test]
+fn balanced_currents_are_constant_in_the_rotor_frame() {
+    for angle in angles() {
+        let a = 2.0 * angle.cos();
+        let b = 2.0 * (angle - 2.0 * PI / 3.0).cos();
+        let (sin, cos) = angle.sin_cos();
+
+        let dq = park(clarke(a, b), sin, cos);
+        assert_close(dq.d, 2.0, 1e-4);
+        assert_close(dq.q, 0.0, 1e-4);
+    }
+}
+
+#[test]
+fn svpwm_stays_within_the_rails() {
+    // Up to the largest undistorted magnitude, and past it where the duties have to clip
+    for &magnitude in [0.0, 0.3, 0.577, 0.8, 2.0].iter() {
+        for angle in angles() {
+            let v = AlphaBeta {
+                alpha: magnitude * angle.cos(),
+                beta: magnitude * angle.sin(),
+            };
+            for &duty in svpwm(v).iter() {
+                assert!((0.0..=1.0).contains(&duty), "{} at {}", duty, angle);
+            }
+        }
+    }
+}
+
+#[test]
+fn svpwm_produces_the_requested_voltage() {
+    for angle in angles() {
+        let v = AlphaBeta {
+            alpha: 0.5 * angle.cos(),
+            beta: 0.5 * angle.sin(),
+        };
+        let [a, b, c] = svpwm(v);
+
+        // Only the differences between phases reach the motor
+        let produced = clarke(a - (a + b + c) / 3.0, b - (a + b + c) / 3.0);
+        assert_close(produced.alpha, v.alpha, 1e-4);
+        assert_close(produced.beta, v.beta, 1e-4);
+    }
+}
+
+/// Step through sectors in `next` order from `start`, `periods` PWM periods each
+fn spin(angle: &mut HallAngle, start: CommutationState, sectors: usize, periods: u32) {
+    let mut position = start;
+    for _ in 0..sectors {
+        for _ in 0..periods {
+            angle.update();
+        }
+        position = position.next();
+        angle.edge(position);
+    }
+}
+
+#[test]
+fn hall_angle_starts_at_the_sector_centre() {
+    let mut angle = HallAngle::new();
+    angle.reset(CommutationState::BC);
+    assert_eq!(angle.update(), CommutationState::BC.angle());
+
+    // One edge gives a direction but no sector time yet
+    angle.edge(CommutationState::BA);
+    assert_eq!(angle.update(), CommutationState::BA.angle());
+}
+
+#[test]
+fn hall_angle_interpolates_through_a_sector() {
+    let mut angle = HallAngle::new();
+    angle.reset(CommutationState::AB);
+    spin(&mut angle, CommutationState::AB, 2, 10);
+
+    // Entered BC at its leading edge, a sixth of a turn after AC's
+    let edge = CommutationState::BC.angle() - FRAC_PI_6;
+    for period in 1..=10 {
+        assert_close(
+            angle.update(),
+            edge + FRAC_PI_3 * period as f32 / 10.0,
+            1e-5,
+        );
+    }
+    // Held at the far edge if the next one is late
+    assert_close(angle.update(), edge + FRAC_PI_3, 1e-5);
+}
+
+#[test]
+fn hall_angle_is_continuous_across_the_wrap() {
+    let mut angle = HallAngle::new();
+    angle.reset(CommutationState::AC);
+    spin(&mut angle, CommutationState::AC, 2, 10);
+
+    // BA ends at π and CA starts at -π, so the angle wraps without jumping the rotor
+    let mut last = 0.0;
+    for _ in 0..10 {
+        last = angle.update();
+    }
+    assert_close(last, PI, 1e-5);
+
+    angle.edge(CommutationState::CA);
+    let first = angle.update();
+    assert_close(first, -PI + FRAC_PI_3 / 10.0, 1e-5);
+    let (sin, cos) = sin_cos(first - last);
+    assert_close(sin, (FRAC_PI_3 / 10.0).sin(), 0.002);
+    assert_close(cos, (FRAC_PI_3 / 10.0).cos(), 0.002);
+}
+
+#[test]
+fn hall_angle_forgets_the_speed_on_reversal() {
+    let mut angle = HallAngle::new();
+    angle.reset(CommutationState::AB);
+    spin(&mut angle, CommutationState::AB, 3, 10);
+
+    angle.edge(CommutationState::BC);
+    assert_eq!(angle.update(), CommutationState::BC.angle());
+
+    // Backwards through a whole sector, then interpolating down from the edge
+    for _ in 0..9 {
+        angle.update();
+    }
+    angle.edge(CommutationState::AC);
+    let edge = CommutationState::AC.angle() + FRAC_PI_6;
+    assert_close(angle.update(), edge - FRAC_PI_3 / 10.0, 1e-5);
+}
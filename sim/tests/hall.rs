@@ -0,0 +1,31 @@
+use crankshaft::{hall::HallTable, motor::CommutationState};
+
+#[test]
+fn table_survives_storage() {
+    let table = HallTable([
+        None,
+        Some(CommutationState::CB),
+        Some(CommutationState::BA),
+        Some(CommutationState::CA),
+        Some(CommutationState::AC),
+        Some(CommutationState::AB),
+        Some(CommutationState::BC),
+        None,
+    ]);
+
+    let stored = HallTable::from_word(table.to_word()).unwrap();
+    assert_eq!(stored.0, table.0);
+    assert_eq!(
+        HallTable::from_word(HallTable::default().to_word())
+            .unwrap()
+            .0,
+        HallTable::default().0
+    );
+}
+
+#[test]
+fn erased_flash_is_not_a_table() {
+    assert!(HallTable::from_word(0xffff_ffff).is_none());
+    // 0b111 is not a state
+    assert!(HallTable::from_word(0b111 << 3).is_none());
+}
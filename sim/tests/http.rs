@@ -0,0 +1,387 @@
+use crankshaft::http::{
+    self, parse, route, Error, Header, Method, Request, Response, Route, Routed, Status, Version,
+};
+
+const CAPACITY: usize = 1024;
+
+const POST: &[u8] = b"POST /speed?erpm=1000 HTTP/1.1\r\n\
+    Host: 192.168.1.2\r\n\
+    Content-Type: text/plain\r\n\
+    Content-Length: 5\r\n\
+    \r\n\
+    hello";
+
+/// Headers the tests look at, the rest are skipped
+const WANTED: &[&str] = &[
+    "Host",
+    "Content-Type",
+    "Content-Length",
+    "Referer",
+    "Connection",
+    "X-Header",
+];
+
+/// Parse with room for eight of the `WANTED` headers, passing the result to `check`
+fn with_parsed<T>(
+    input: &[u8],
+    capacity: usize,
+    check: impl FnOnce(Result<Status, Error>) -> T,
+) -> T {
+    let mut headers = [Header::EMPTY; 8];
+    check(parse(input, capacity, WANTED, &mut headers))
+}
+
+fn complete<T>(input: &[u8], check: impl FnOnce(&Request, usize) -> T) -> T {
+    with_parsed(input, CAPACITY, |result| match result {
+        Ok(Status::Complete(request, length)) => check(&request, length),
+        other => panic!("{:?}", other),
+    })
+}
+
+fn error(input: &[u8], capacity: usize) -> Error {
+    with_parsed(input, capacity, |result| match result {
+        Err(error) => error,
+        other => panic!("{:?}", other),
+    })
+}
+
+fn is_partial(input: &[u8]) -> bool {
+    with_parsed(input, CAPACITY, |result| {
+        matches!(result, Ok(Status::Partial))
+    })
+}
+
+#[test]
+fn parses_request_with_body() {
+    complete(POST, |request, length| {
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.target, "/speed?erpm=1000");
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.headers.len(), 3);
+        assert_eq!(request.header("content-type"), Some("text/plain"));
+        assert_eq!(request.body, b"hello");
+        assert_eq!(length, POST.len());
+    });
+}
+
+#[test]
+fn accepts_bare_line_feeds() {
+    complete(b"GET / HTTP/1.0\nHost: x\n\n", |request, length| {
+        assert_eq!(request.version, Version::Http10);
+        assert_eq!(request.header("Host"), Some("x"));
+        assert!(request.body.is_empty());
+        assert_eq!(length, 24);
+    });
+}
+
+#[test]
+fn keeps_colons_in_header_values() {
+    complete(
+        b"GET / HTTP/1.1\r\nReferer:  http://192.168.1.2:80/ \t\r\n\r\n",
+        |request, _| assert_eq!(request.header("referer"), Some("http://192.168.1.2:80/")),
+    );
+}
+
+#[test]
+fn skips_empty_lines_before_request() {
+    complete(b"\r\n\r\nGET /status HTTP/1.1\r\n\r\n", |request, _| {
+        assert_eq!(request.target, "/status")
+    });
+}
+
+#[test]
+fn keeps_alive_by_version_and_connection_header() {
+    let cases: &[(&[u8], bool)] = &[
+        (b"GET / HTTP/1.1\r\n\r\n", true),
+        (b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n", false),
+        (
+            b"GET / HTTP/1.1\r\nConnection: Upgrade, Close\r\n\r\n",
+            false,
+        ),
+        (b"GET / HTTP/1.0\r\n\r\n", false),
+        (b"GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n", true),
+        (
+            b"GET / HTTP/1.0\r\nConnection: close\r\nConnection: keep-alive\r\n\r\n",
+            true,
+        ),
+    ];
+
+    for &(input, keep_alive) in cases {
+        complete(input, |request, _| {
+            assert_eq!(
+                request.keep_alive(),
+                keep_alive,
+                "{:?}",
+                String::from_utf8_lossy(input)
+            )
+        });
+    }
+}
+
+#[test]
+fn waits_for_every_split_of_request() {
+    for end in 0..POST.len() {
+        assert!(is_partial(&POST[..end]), "{} bytes", end);
+    }
+}
+
+#[test]
+fn leaves_pipelined_request_alone() {
+    let mut input = POST.to_vec();
+    input.extend_from_slice(b"GET / HTTP/1.1\r\n");
+
+    complete(&input, |request, length| {
+        assert_eq!(request.body, b"hello");
+        assert_eq!(length, POST.len());
+    });
+}
+
+#[test]
+fn rejects_malformed_requests() {
+    let requests: &[&[u8]] = &[
+        b"GET\r\n\r\n",
+        b"GET /\r\n\r\n",
+        b"GET  / HTTP/1.1\r\n\r\n",
+        b"GET / HTTP/1.1 extra\r\n\r\n",
+        b"GET / HTTP/2.0\r\n\r\n",
+        b"G(T / HTTP/1.1\r\n\r\n",
+        b"GET /\x7f HTTP/1.1\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost : x\r\n\r\n",
+        b"GET / HTTP/1.1\r\n: x\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: x\r\n continued\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: \x01\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: \xff\r\n\r\n",
+        b"GET / HTTP/1.1\r\nHost: x\ry\r\n\r\n",
+        b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+        b"POST / HTTP/1.1\r\nContent-Length: +1\r\n\r\nx",
+        b"POST / HTTP/1.1\r\nContent-Length:\r\n\r\n",
+        b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nxx",
+        b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+    ];
+
+    for request in requests {
+        assert_eq!(
+            error(request, CAPACITY),
+            Error::BadRequest,
+            "{:?}",
+            String::from_utf8_lossy(request)
+        );
+    }
+}
+
+#[test]
+fn rejects_bad_line_before_rest_arrives() {
+    assert_eq!(error(b"GET / SPDY\r\nHost", CAPACITY), Error::BadRequest);
+}
+
+#[test]
+fn limits_headers() {
+    let mut input = b"GET / HTTP/1.1\r\n".to_vec();
+    for _ in 0..9 {
+        input.extend_from_slice(b"X-Header: value\r\n");
+    }
+    assert_eq!(error(&input, CAPACITY), Error::HeadersTooLarge);
+    assert_eq!(Error::HeadersTooLarge.status(), 431);
+
+    let head = b"GET / HTTP/1.1\r\nCookie: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    assert_eq!(error(head, 32), Error::HeadersTooLarge);
+}
+
+#[test]
+fn skips_headers_not_wanted() {
+    let mut input = b"GET / HTTP/1.1\r\nHost: x\r\n".to_vec();
+    for i in 0..40 {
+        input.extend_from_slice(format!("Cookie-{}: value\r\n", i).as_bytes());
+    }
+    input.extend_from_slice(b"Connection: close\r\n\r\n");
+
+    complete(&input, |request, length| {
+        assert_eq!(request.headers.len(), 2);
+        assert_eq!(request.header("host"), Some("x"));
+        assert_eq!(request.header("cookie-0"), None);
+        assert!(!request.keep_alive());
+        assert_eq!(length, input.len());
+    });
+
+    // Even skipped headers must be well formed
+    assert_eq!(
+        error(b"GET / HTTP/1.1\r\nCookie : x\r\n\r\n", CAPACITY),
+        Error::BadRequest
+    );
+}
+
+#[test]
+fn limits_request_line() {
+    let mut line = b"GET /".to_vec();
+    line.resize(64, b'a');
+    assert_eq!(error(&line, 64), Error::UriTooLong);
+    assert_eq!(Error::UriTooLong.status(), 414);
+    assert!(is_partial(&line));
+
+    // Once the request line is complete, running out of room is down to the headers
+    line.extend_from_slice(b" HTTP/1.1\r\nHost: x");
+    assert_eq!(error(&line, line.len()), Error::HeadersTooLarge);
+}
+
+#[test]
+fn limits_body() {
+    let head = b"POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n";
+    assert_eq!(error(head, 64), Error::PayloadTooLarge);
+    assert_eq!(Error::PayloadTooLarge.status(), 413);
+
+    let huge = b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n";
+    assert_eq!(error(huge, CAPACITY), Error::PayloadTooLarge);
+}
+
+const ROUTES: &[Route<u8>] = &[
+    Route {
+        method: Method::Get,
+        path: "/",
+        handler: 0,
+    },
+    Route {
+        method: Method::Get,
+        path: "/identify",
+        handler: 1,
+    },
+    Route {
+        method: Method::Post,
+        path: "/identify",
+        handler: 2,
+    },
+    Route {
+        method: Method::Post,
+        path: "/arm",
+        handler: 3,
+    },
+];
+
+#[test]
+fn routes_by_method_and_path() {
+    assert_eq!(route(ROUTES, "GET", "/"), Routed::Found(0));
+    assert_eq!(route(ROUTES, "GET", "/identify"), Routed::Found(1));
+    assert_eq!(route(ROUTES, "POST", "/identify"), Routed::Found(2));
+    assert_eq!(route(ROUTES, "HEAD", "/identify"), Routed::Found(1));
+    assert_eq!(route(ROUTES, "GET", "/nowhere"), Routed::NotFound);
+    assert_eq!(route(ROUTES, "GET", "/identify/"), Routed::NotFound);
+}
+
+#[test]
+fn lists_allowed_methods() {
+    let allowed = |method, path| match route(ROUTES, method, path) {
+        Routed::MethodNotAllowed(allow) => allow.to_string(),
+        other => panic!("{:?}", other),
+    };
+
+    assert_eq!(allowed("GET", "/arm"), "POST");
+    assert_eq!(allowed("HEAD", "/arm"), "POST");
+    assert_eq!(allowed("POST", "/"), "GET, HEAD");
+    assert_eq!(allowed("PUT", "/identify"), "GET, HEAD, POST");
+}
+
+#[test]
+fn writes_response_heads() {
+    let head = Response {
+        status: 200,
+        content_type: Some("application/json"),
+        content_length: 2,
+        keep_alive: true,
+        allow: None,
+        headers: &[Header {
+            name: "Access-Control-Allow-Origin",
+            value: "*",
+        }],
+    };
+    assert_eq!(
+        head.to_string(),
+        "HTTP/1.1 200 OK\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: 2\r\n\
+         Connection: keep-alive\r\n\
+         Access-Control-Allow-Origin: *\r\n\
+         \r\n"
+    );
+
+    let allow = match route(ROUTES, "DELETE", "/arm") {
+        Routed::MethodNotAllowed(allow) => allow,
+        other => panic!("{:?}", other),
+    };
+    let head = Response {
+        allow: Some(allow),
+        ..Response::empty(405)
+    };
+    assert_eq!(
+        head.to_string(),
+        "HTTP/1.1 405 Method Not Allowed\r\n\
+         Content-Length: 0\r\n\
+         Connection: close\r\n\
+         Allow: POST\r\n\
+         \r\n"
+    );
+
+    assert!(Response::empty(Error::HeadersTooLarge.status())
+        .to_string()
+        .starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"));
+    assert!(Response::empty(Error::UriTooLong.status())
+        .to_string()
+        .starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+}
+
+#[test]
+fn measures_bodies() {
+    assert_eq!(http::length(&""), 0);
+    assert_eq!(
+        http::length(&format_args!("{{\"erpm\": {:.0}}}", 1234.5)),
+        14
+    );
+}
+
+/// Xorshift, so that failures reproduce
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        self.next() as usize % n
+    }
+}
+
+#[test]
+fn survives_mutated_requests() {
+    let mut random = Random(0x2545_f491);
+    let alphabet = b"\r\n :\t0aZ/?\x00\x7f\xff";
+
+    for _ in 0..100_000 {
+        let mut input = POST.to_vec();
+        for _ in 0..=random.below(4) {
+            let at = random.below(input.len() + 1);
+            let byte = match random.below(2) {
+                0 => alphabet[random.below(alphabet.len())],
+                _ => random.next() as u8,
+            };
+            match random.below(3) {
+                0 if at < input.len() => input[at] = byte,
+                1 if at < input.len() => {
+                    input.remove(at);
+                }
+                _ => input.insert(at, byte),
+            }
+        }
+        let capacity = random.below(2 * POST.len());
+        let input = &input[..random.below(input.len() + 1)];
+
+        with_parsed(input, capacity, |result| {
+            if let Ok(Status::Complete(request, length)) = result {
+                assert!(length <= input.len() && length <= capacity);
+                assert!(request.body.len() <= length);
+            }
+        });
+    }
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use {
+    common::{Rig, PWM_HZ},
+    crankshaft::identify::{Identify, IdentifyError, MotorParameters},
+    crankshaft_sim::{Gate, Motor},
+};
+
+/// Run identification to the end as `ADC` does, giving up after `seconds`
+fn identify(rig: &mut Rig, seconds: f32) -> Result<MotorParameters, IdentifyError> {
+    let dt = 1.0 / PWM_HZ as f32;
+    let mut identify = Identify::new();
+    identify.start();
+
+    for _ in 0..(seconds * PWM_HZ as f32) as u32 {
+        rig.period();
+        let samples = rig.sim.samples();
+        let sim = &rig.sim;
+        identify.update(&mut rig.driver, &samples, dt, || {
+            let [a, b, _] = sim.phase_voltage();
+            a - b
+        });
+
+        if !identify.is_running() {
+            assert_eq!(rig.sim.gates(), [Gate::Floating; 3]);
+            return identify.result().unwrap();
+        }
+    }
+
+    panic!("still identifying after {}s", seconds);
+}
+
+fn assert_within(measured: f32, actual: f32, fraction: f32) {
+    assert!(
+        (measured - actual).abs() <= actual * fraction,
+        "measured {} for {}",
+        measured,
+        actual
+    );
+}
+
+#[test]
+fn measures_the_motor() {
+    let motor = Motor::default();
+    let mut rig = Rig::with_motor(motor);
+
+    let parameters = identify(&mut rig, 10.0).unwrap();
+    assert_within(parameters.resistance, motor.resistance, 0.1);
+    assert_within(parameters.inductance, motor.inductance, 0.1);
+    assert_within(parameters.flux_linkage, motor.flux_linkage, 0.1);
+}
+
+#[test]
+fn locked_rotor_has_no_back_emf() {
+    let mut rig = Rig::new();
+    rig.sim.set_locked(true);
+
+    match identify(&mut rig, 10.0) {
+        Err(IdentifyError::NoBackEmf) => {}
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+fn open_phases_carry_no_current() {
+    let mut rig = Rig::new();
+    rig.sim.set_bus_voltage(0.0);
+
+    match identify(&mut rig, 10.0) {
+        Err(IdentifyError::NoCurrent) => {}
+        result => panic!("{:?}", result),
+    }
+}
@@ -0,0 +1,19 @@
+use crankshaft::load::Load;
+
+#[test]
+fn averages_over_whole_windows() {
+    let mut load = Load::new(1_000);
+    assert_eq!(load.load(), 0.0);
+
+    for _ in 0..9 {
+        load.record(25, 75);
+    }
+    // Not until the window is complete
+    assert_eq!(load.load(), 0.0);
+    load.record(25, 75);
+    assert_eq!(load.load(), 0.25);
+
+    // A long stretch awake is measured whole, not split across windows
+    load.record(2_900, 100);
+    assert_eq!(load.load(), 2_900.0 / 3_000.0);
+}
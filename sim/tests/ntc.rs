@@ -0,0 +1,178 @@
+use {
+    crankshaft::{
+        config,
+        fault::{Fault, Faults, Limits},
+        ntc::{ln, Derating, Divider, Model, Ntc, Temperatures},
+    },
+    std::f32::consts::E,
+};
+
+const BETA: Model = Model::Beta {
+    r25: 10_000.0,
+    beta: 3_380.0,
+};
+/// A common 10K thermistor
+const STEINHART_HART: Model = Model::SteinhartHart {
+    a: 1.009_249_5e-3,
+    b: 2.378_405_4e-4,
+    c: 2.019_202_7e-7,
+};
+
+fn ntc(model: Model, divider: Divider) -> Ntc {
+    Ntc {
+        model,
+        divider,
+        resistor: 10_000.0,
+    }
+}
+
+/// Divider output for a thermistor of `resistance` ohms
+fn ratio(divider: Divider, resistance: f64) -> f32 {
+    (match divider {
+        Divider::LowSide => resistance / (resistance + 10_000.0),
+        Divider::HighSide => 10_000.0 / (resistance + 10_000.0),
+    }) as f32
+}
+
+fn assert_close(actual: f32, expected: f64, tolerance: f64) {
+    assert!(
+        (f64::from(actual) - expected).abs() <= tolerance,
+        "{} is not within {} of {}",
+        actual,
+        tolerance,
+        expected
+    );
+}
+
+#[test]
+fn ln_matches_std() {
+    for &x in [1e-6, 0.01, 0.5, 1.0, 2.0, E, 100.0, 10_000.0, 3e7].iter() {
+        assert_close(
+            ln(x),
+            f64::from(x).ln(),
+            1e-5 * f64::from(x).ln().abs().max(1.0),
+        );
+    }
+}
+
+#[test]
+fn beta_model_in_either_divider() {
+    for &divider in [Divider::LowSide, Divider::HighSide].iter() {
+        let ntc = ntc(BETA, divider);
+
+        // Equal halves at 25°C
+        assert_close(ntc.temperature(0.5).unwrap(), 25.0, 0.01);
+
+        for &celsius in [-20.0f64, 0.0, 50.0, 85.0, 120.0].iter() {
+            let kelvin = celsius + 273.15;
+            let resistance = 10_000.0 * (3_380.0 * (1.0 / kelvin - 1.0 / 298.15)).exp();
+            let measured = ntc.temperature(ratio(divider, resistance)).unwrap();
+            assert_close(measured, celsius, 0.05);
+        }
+    }
+}
+
+#[test]
+fn steinhart_hart_model_in_either_divider() {
+    for &divider in [Divider::LowSide, Divider::HighSide].iter() {
+        let ntc = ntc(STEINHART_HART, divider);
+
+        for &resistance in [500.0, 3_000.0, 10_000.0, 30_000.0, 200_000.0].iter() {
+            let ln_r = f64::ln(resistance);
+            let expected = 1.0
+                / (1.009_249_5e-3 + 2.378_405_4e-4 * ln_r + 2.019_202_7e-7 * ln_r.powi(3))
+                - 273.15;
+            let measured = ntc.temperature(ratio(divider, resistance)).unwrap();
+            assert_close(measured, expected, 0.05);
+        }
+    }
+}
+
+#[test]
+fn divider_orientation_matters() {
+    // A hot thermistor pulls a low side divider down and a high side one up
+    let low = ntc(BETA, Divider::LowSide).temperature(0.2).unwrap();
+    let high = ntc(BETA, Divider::HighSide).temperature(0.2).unwrap();
+    assert!(low > 50.0, "{}", low);
+    assert!(high < 0.0, "{}", high);
+}
+
+#[test]
+fn readings_at_the_rails_are_faults() {
+    for &divider in [Divider::LowSide, Divider::HighSide].iter() {
+        for &model in [BETA, STEINHART_HART].iter() {
+            let ntc = ntc(model, divider);
+            for &ratio in [0.0, 0.001, 0.999, 1.0, -0.5, 1.5, core::f32::NAN].iter() {
+                assert_eq!(ntc.temperature(ratio), None, "{}", ratio);
+            }
+        }
+    }
+
+    let mut faults = Faults::new(config::LIMITS);
+    let temperatures = Temperatures {
+        fet: Some(40.0),
+        motor: None,
+    };
+    assert_eq!(
+        faults.check_temperatures(&temperatures),
+        Some(Fault::Thermistor)
+    );
+}
+
+#[test]
+fn open_motor_input_without_a_motor_thermistor() {
+    // The pull-up takes an empty low side input to the top of the ADC range
+    let open = ntc(BETA, Divider::LowSide).temperature(4095.0 / 4096.0);
+    assert_eq!(open, None);
+    let temperatures = Temperatures {
+        fet: Some(40.0),
+        motor: open,
+    };
+
+    let mut faults = Faults::new(Limits {
+        max_motor_temperature: None,
+        ..config::LIMITS
+    });
+    for _ in 0..10 {
+        assert_eq!(faults.check_temperatures(&temperatures), None);
+    }
+
+    // The FET thermistor is always checked
+    let temperatures = Temperatures {
+        fet: None,
+        motor: open,
+    };
+    assert_eq!(
+        faults.check_temperatures(&temperatures),
+        Some(Fault::Thermistor)
+    );
+
+    // A motor thermistor that is expected must be there
+    let mut faults = Faults::new(Limits {
+        max_motor_temperature: Some(100.0),
+        ..config::LIMITS
+    });
+    let temperatures = Temperatures {
+        fet: Some(40.0),
+        motor: open,
+    };
+    assert_eq!(
+        faults.check_temperatures(&temperatures),
+        Some(Fault::Thermistor)
+    );
+}
+
+#[test]
+fn derating_is_linear_between_the_limits() {
+    let derating = Derating {
+        start: 70.0,
+        end: 90.0,
+    };
+
+    assert_eq!(derating.factor(-40.0), 1.0);
+    assert_eq!(derating.factor(70.0), 1.0);
+    assert_eq!(derating.factor(75.0), 0.75);
+    assert_eq!(derating.factor(80.0), 0.5);
+    assert_eq!(derating.factor(90.0), 0.0);
+    assert_eq!(derating.factor(150.0), 0.0);
+}
@@ -0,0 +1,131 @@
+use crankshaft::{
+    motor::{BrakeMode, ControlState},
+    ramp::{Ramp, Rates},
+};
+
+const RATES: Rates = Rates {
+    duty: 1.0,
+    current: 100.0,
+    speed: 10_000.0,
+};
+const DT: f32 = 0.01;
+
+fn ramp() -> Ramp {
+    Ramp::new(ControlState::Duty(0.5), RATES, BrakeMode::Dynamic(0.25))
+}
+
+fn duty(state: ControlState) -> f32 {
+    match state {
+        ControlState::Duty(duty) => duty,
+        state => panic!("{:?} is not a duty cycle", state),
+    }
+}
+
+#[test]
+fn duty_ramps_at_the_rate() {
+    let mut ramp = ramp();
+    for step in 1..=50 {
+        let applied = duty(ramp.update(ControlState::Forward, 0.0, DT));
+        assert!((applied - step as f32 * 0.01).abs() < 1e-4, "{}", applied);
+    }
+    assert_eq!(duty(ramp.update(ControlState::Forward, 0.0, DT)), 0.5);
+}
+
+#[test]
+fn duty_reverses_through_zero_and_a_stop() {
+    let mut ramp = ramp();
+    for _ in 0..100 {
+        ramp.update(ControlState::Forward, 1_000.0, DT);
+    }
+
+    // Down to zero without ever driving backwards against the still turning rotor
+    let mut previous = 0.5;
+    loop {
+        let applied = duty(ramp.update(ControlState::Reverse, 1_000.0, DT));
+        assert!(applied >= 0.0 && applied < previous, "{}", applied);
+        previous = applied;
+        if applied == 0.0 {
+            break;
+        }
+    }
+
+    // Braking until the rotor stops
+    for _ in 0..10 {
+        match ramp.update(ControlState::Reverse, 500.0, DT) {
+            ControlState::Brake(BrakeMode::Dynamic(_)) => {}
+            state => panic!("{:?} while still turning", state),
+        }
+    }
+
+    // Then ramping up the other way
+    let applied = duty(ramp.update(ControlState::Reverse, 0.0, DT));
+    assert!((applied + 0.01).abs() < 1e-4, "{}", applied);
+    for _ in 0..100 {
+        ramp.update(ControlState::Reverse, -1_000.0, DT);
+    }
+    assert_eq!(duty(ramp.applied()), -0.5);
+}
+
+#[test]
+fn speed_reversal_stops_at_zero() {
+    let mut ramp = ramp();
+    for _ in 0..100 {
+        ramp.update(ControlState::Speed(500.0), 500.0, DT);
+    }
+
+    let mut crossed = false;
+    for _ in 0..100 {
+        match ramp.update(ControlState::Speed(-500.0), 500.0, DT) {
+            ControlState::Speed(erpm) => {
+                assert!(erpm >= 0.0, "{}", erpm);
+                assert!(!crossed, "drove again before the rotor stopped");
+            }
+            ControlState::Brake(_) => crossed = true,
+            state => panic!("{:?}", state),
+        }
+    }
+    assert!(crossed);
+}
+
+#[test]
+fn current_passes_straight_through_zero() {
+    let mut ramp = ramp();
+    for _ in 0..100 {
+        ramp.update(ControlState::Current(10.0), 1_000.0, DT);
+    }
+
+    // Reverse current regenerates, so there is no stop at zero
+    let mut previous = 10.0;
+    for _ in 0..20 {
+        match ramp.update(ControlState::Current(-10.0), 1_000.0, DT) {
+            ControlState::Current(amps) => {
+                assert!(
+                    (previous - amps - 1.0).abs() < 1e-4,
+                    "{} after {}",
+                    amps,
+                    previous
+                );
+                previous = amps;
+            }
+            state => panic!("{:?}", state),
+        }
+    }
+    assert!((previous + 10.0).abs() < 1e-4);
+}
+
+#[test]
+fn states_without_a_setpoint_apply_at_once() {
+    let mut ramp = ramp();
+    for _ in 0..100 {
+        ramp.update(ControlState::Forward, 1_000.0, DT);
+    }
+
+    match ramp.update(ControlState::Brake(BrakeMode::Short), 1_000.0, DT) {
+        ControlState::Brake(BrakeMode::Short) => {}
+        state => panic!("{:?}", state),
+    }
+    match ramp.update(ControlState::Idle, 1_000.0, DT) {
+        ControlState::Idle => {}
+        state => panic!("{:?}", state),
+    }
+}
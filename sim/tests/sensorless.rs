@@ -0,0 +1,84 @@
+mod common;
+
+use {
+    common::{periods, Rig},
+    crankshaft::sensorless::{self, Action, Sensorless, Stage},
+    crankshaft_sim::Motor,
+};
+
+/// Open-loop startup only synchronises when the back-EMF at the end of the ramp is close to what
+/// `STARTUP_DUTY` applies, so the ramp is tuned to the motor
+fn matched_motor() -> Motor {
+    Motor {
+        flux_linkage: 0.0045,
+        ..Motor::default()
+    }
+}
+
+/// Start as `motor_task` does
+fn start(rig: &mut Rig, sensorless: &mut Sensorless, direction: bool) {
+    rig.driver.set_duty(sensorless::STARTUP_DUTY);
+    let state = sensorless.start(direction);
+    rig.driver.hold(state, sensorless::STARTUP_DUTY);
+}
+
+/// `TIM1_UP_TIM10`, with the phase voltages in the same units as the bus voltage
+fn update(rig: &mut Rig, sensorless: &mut Sensorless) {
+    let volts = rig.sim.phase_voltage();
+    let floating = (volts[sensorless.floating()] * 100.0) as u16;
+    let bus = (rig.sim.bus_voltage() * 100.0) as u16;
+
+    match sensorless.update(floating, bus) {
+        Action::None => (),
+        Action::Commutate(state) => {
+            rig.tachometer.edge(state);
+            match sensorless.stage() {
+                Stage::Running => rig.driver.commutate(state),
+                _ => rig.driver.hold(state, sensorless::STARTUP_DUTY),
+            }
+        }
+        Action::Coast => rig.driver.set_idle(),
+    }
+}
+
+#[test]
+fn startup_hands_over_to_closed_loop() {
+    for &(direction, sign) in [(false, 1.0), (true, -1.0)].iter() {
+        let mut rig = Rig::with_motor(matched_motor());
+        let mut sensorless = Sensorless::new();
+
+        start(&mut rig, &mut sensorless, direction);
+        for _ in 0..periods(4.0) {
+            rig.period();
+            update(&mut rig, &mut sensorless);
+
+            // `motor_task` takes over the duty cycle once running
+            if rig.task_due() && sensorless.stage() == Stage::Running {
+                rig.driver.set_duty(0.2);
+            }
+        }
+
+        assert_eq!(sensorless.stage(), Stage::Running);
+        let erpm = rig.sim.erpm() * sign;
+        assert!(erpm > 2_000.0, "{} erpm", erpm);
+        assert!(rig.fault().is_none());
+    }
+}
+
+#[test]
+fn jammed_rotor_loses_sync() {
+    let mut rig = Rig::with_motor(matched_motor());
+    rig.sim.set_locked(true);
+    let mut sensorless = Sensorless::new();
+
+    start(&mut rig, &mut sensorless, false);
+    for _ in 0..periods(10.0) {
+        rig.period();
+        update(&mut rig, &mut sensorless);
+        if sensorless.stage() == Stage::Lost {
+            break;
+        }
+    }
+
+    assert_eq!(sensorless.stage(), Stage::Lost);
+}
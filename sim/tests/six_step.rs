@@ -0,0 +1,149 @@
+mod common;
+
+use {
+    common::{periods, Rig, MOTOR_TASK_HZ},
+    crankshaft::{
+        config,
+        hall::HallTable,
+        motor::{BrakeMode, HallDetector},
+        pid::Pid,
+    },
+};
+
+/// Spin up under hall commutation at a fixed duty cycle
+fn spin(rig: &mut Rig, direction: bool, duty: f32, seconds: f32) {
+    rig.driver.set_duty(duty);
+    rig.commutate_hall(direction);
+    for _ in 0..periods(seconds) {
+        if rig.period().is_some() {
+            rig.commutate_hall(direction);
+        }
+    }
+}
+
+#[test]
+fn hall_detection_finds_wiring() {
+    // Sensors mounted a sixth of an electrical revolution round from the usual position
+    let mut wiring = HallTable::default();
+    for entry in wiring.0.iter_mut() {
+        *entry = entry.map(|state| state.next());
+    }
+
+    let mut rig = Rig::new();
+    rig.sim.set_hall_wiring(wiring);
+    let mut detector = HallDetector::new();
+
+    let table = loop {
+        for _ in 0..common::TASK_PERIODS {
+            rig.period();
+        }
+        if let Some(result) = detector.update(&mut rig.driver, rig.hall.code()) {
+            break result.expect("detection failed");
+        }
+    };
+
+    assert_eq!(table.0, wiring.0);
+}
+
+#[test]
+fn hall_detection_reports_disconnected_sensor() {
+    let mut rig = Rig::new();
+    rig.sim.set_hall_stuck(2, Some(false));
+    let mut detector = HallDetector::new();
+
+    let result = loop {
+        for _ in 0..common::TASK_PERIODS {
+            rig.period();
+        }
+        if let Some(result) = detector.update(&mut rig.driver, rig.hall.code()) {
+            break result;
+        }
+    };
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn hall_commutation_spins_both_ways() {
+    for &(direction, sign) in [(false, 1.0), (true, -1.0)].iter() {
+        let mut rig = Rig::new();
+        spin(&mut rig, direction, 0.3, 1.0);
+
+        let erpm = rig.sim.erpm() * sign;
+        assert!(erpm > 5_000.0, "{} erpm", erpm);
+        assert!(
+            (rig.tachometer.erpm() * sign - erpm).abs() < erpm * 0.1,
+            "measured {} erpm at {} erpm",
+            rig.tachometer.erpm(),
+            rig.sim.erpm()
+        );
+        assert!(rig.fault().is_none());
+    }
+}
+
+#[test]
+fn speed_loop_holds_setpoint_under_load() {
+    let mut rig = Rig::new();
+    rig.sim.set_load(0.05);
+    let mut speed_loop = Pid::new(
+        config::MOTOR.speed_kp,
+        config::MOTOR.speed_ki,
+        config::MOTOR.speed_kd,
+        config::MOTOR.speed_integral_limit,
+        config::MOTOR.speed_output_min,
+        config::MOTOR.speed_output_max,
+    );
+    let setpoint = 4_000.0;
+    let dt = 1.0 / MOTOR_TASK_HZ as f32;
+
+    rig.commutate_hall(false);
+    for _ in 0..periods(4.0) {
+        if rig.period().is_some() {
+            rig.commutate_hall(false);
+        }
+        if rig.task_due() {
+            let duty = speed_loop.update(setpoint, rig.tachometer.erpm(), dt);
+            rig.driver.set_duty(duty);
+            // Only matters at standstill, when there are no edges
+            rig.commutate_hall(false);
+        }
+    }
+
+    let erpm = rig.sim.erpm();
+    assert!((erpm - setpoint).abs() < setpoint * 0.05, "{} erpm", erpm);
+}
+
+#[test]
+fn dynamic_brake_stops_faster_than_coasting() {
+    let stop = |mode| {
+        let mut rig = Rig::new();
+        spin(&mut rig, false, 0.3, 1.0);
+        let start = rig.sim.erpm();
+
+        rig.driver.brake(mode);
+        for _ in 0..periods(0.2) {
+            rig.period();
+        }
+        assert!(rig.fault().is_none());
+        rig.sim.erpm() / start
+    };
+
+    let coasting = stop(BrakeMode::Coast);
+    let braking = stop(BrakeMode::Dynamic(0.5));
+    assert!(
+        braking < coasting * 0.8,
+        "{} braking, {} coasting",
+        braking,
+        coasting
+    );
+}
+
+#[test]
+fn dynamic_brake_duty_is_clamped() {
+    let mut rig = Rig::new();
+
+    rig.driver.brake(BrakeMode::Dynamic(1.5));
+    assert_eq!(rig.sim.duties(), [1.0; 3]);
+    rig.driver.brake(BrakeMode::Dynamic(-0.5));
+    assert_eq!(rig.sim.duties(), [0.0; 3]);
+}
@@ -0,0 +1,199 @@
+use {
+    crankshaft::Samples,
+    stm32f4xx_hal::stm32::{ADC1, ADC_COMMON, GPIOA, GPIOB, GPIOC, RCC},
+};
+
+const VREF: f32 = 3.3;
+const FULL_SCALE: f32 = 4095.0;
+/// Shunt amplifier output with no current flowing, before calibration
+const CURRENT_ZERO: f32 = 2048.0;
+/// Readings averaged per channel when calibrating the current offsets
+const CALIBRATION_SAMPLES: u32 = 1024;
+
+/// Analog front end, which differs between boards
+pub struct Scaling {
+    pub shunt_ohms: f32,
+    pub amplifier_gain: f32,
+    /// Ratio of the phase and bus voltage dividers
+    pub voltage_divider: f32,
+}
+
+/// Which ADC1 channel each input is on, which differs between boards
+#[derive(Debug, Clone, Copy)]
+pub struct Channels {
+    /// Phase voltage dividers, indexed by phase
+    pub phase_voltage: [u8; 3],
+    pub bus_voltage: u8,
+    /// Shunt amplifiers for phases A and B
+    pub phase_current: [u8; 2],
+    /// MOSFET and motor thermistor dividers
+    pub fet_temperature: u8,
+    pub motor_temperature: u8,
+}
+
+impl Channels {
+    fn all(&self) -> [u8; 8] {
+        [
+            self.phase_voltage[0],
+            self.phase_voltage[1],
+            self.phase_voltage[2],
+            self.bus_voltage,
+            self.phase_current[0],
+            self.phase_current[1],
+            self.fet_temperature,
+            self.motor_temperature,
+        ]
+    }
+}
+
+pub struct Adc {
+    adc: ADC1,
+    scaling: Scaling,
+    channels: Channels,
+    /// Raw current readings with no current flowing
+    current_offset: [f32; 2],
+}
+
+impl Adc {
+    /// Enable ADC1 for 12-bit conversions at PCLK2 / 2
+    ///
+    /// Regular conversions are started in software by `read`. The phase currents and bus voltage
+    /// are also converted as an injected group on every rising edge of TIM1 channel 4. Every pin
+    /// in `channels` is switched to analog mode.
+    pub fn adc1(adc: ADC1, common: &ADC_COMMON, scaling: Scaling, channels: Channels) -> Self {
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb2enr.modify(|_, w| w.adc1en().set_bit());
+
+        for &channel in channels.all().iter() {
+            set_analog(channel);
+        }
+
+        common.ccr.modify(|_, w| unsafe { w.adcpre().bits(0b00) });
+
+        // 15 cycle sampling on every channel
+        adc.smpr1.write(|w| unsafe { w.bits(0x0124_9249) });
+        adc.smpr2.write(|w| unsafe { w.bits(0x0924_9249) });
+
+        // Three injected conversions, which start from JSQ2 and land in JDR1 to JDR3
+        adc.jsqr.write(|w| unsafe {
+            w.jl()
+                .bits(2)
+                .jsq2()
+                .bits(channels.phase_current[0])
+                .jsq3()
+                .bits(channels.phase_current[1])
+                .jsq4()
+                .bits(channels.bus_voltage)
+        });
+
+        adc.cr1.reset();
+        // Injected group on the rising edge of TIM1_CC4
+        adc.cr2.write(|w| unsafe {
+            w.jexten()
+                .bits(0b01)
+                .jextsel()
+                .bits(0b0000)
+                .adon()
+                .set_bit()
+        });
+
+        Self {
+            adc,
+            scaling,
+            channels,
+            current_offset: [CURRENT_ZERO; 2],
+        }
+    }
+
+    /// Blocking conversion of a single channel
+    pub fn read(&mut self, channel: u8) -> u16 {
+        self.adc.sqr1.reset();
+        self.adc.sqr3.write(|w| unsafe { w.sq1().bits(channel) });
+        self.adc.cr2.modify(|_, w| w.swstart().set_bit());
+
+        while self.adc.sr.read().eoc().bit_is_clear() {}
+
+        self.adc.dr.read().data().bits()
+    }
+
+    /// Measure the current amplifier offsets, which must be done with all phases floating
+    pub fn calibrate(&mut self) {
+        let channels = self.channels.phase_current;
+        for (offset, &channel) in self.current_offset.iter_mut().zip(channels.iter()) {
+            let mut sum = 0;
+            for _ in 0..CALIBRATION_SAMPLES {
+                sum += u32::from(self.read(channel));
+            }
+            *offset = sum as f32 / CALIBRATION_SAMPLES as f32;
+        }
+    }
+
+    pub fn current_offset(&self) -> [f32; 2] {
+        self.current_offset
+    }
+
+    /// Raise the ADC interrupt at the end of every injected group
+    pub fn listen_injected(&mut self) {
+        self.adc.sr.modify(|_, w| w.jeoc().clear_bit());
+        self.adc.cr1.modify(|_, w| w.jeocie().set_bit());
+    }
+
+    /// Acknowledge and scale the latest injected conversions
+    pub fn injected(&mut self) -> Samples {
+        self.adc.sr.modify(|_, w| w.jeoc().clear_bit());
+
+        let a = self.current(0, self.adc.jdr1.read().jdata().bits());
+        let b = self.current(1, self.adc.jdr2.read().jdata().bits());
+        let bus = self.voltage(self.adc.jdr3.read().jdata().bits());
+
+        Samples {
+            phase_current: [a, b, -(a + b)],
+            bus_voltage: bus,
+        }
+    }
+
+    /// Convert a reading from the shunt amplifier on phase A or B to amps
+    pub fn current(&self, phase: usize, counts: u16) -> f32 {
+        (f32::from(counts) - self.current_offset[phase]) * (VREF / FULL_SCALE)
+            / (self.scaling.shunt_ohms * self.scaling.amplifier_gain)
+    }
+
+    /// Convert a phase or bus voltage divider reading to volts
+    pub fn voltage(&self, counts: u16) -> f32 {
+        f32::from(counts) * (VREF / FULL_SCALE) * self.scaling.voltage_divider
+    }
+
+    /// Convert a reading to a fraction of the reference, for ratiometric inputs like thermistors
+    pub fn ratio(&self, counts: u16) -> f32 {
+        f32::from(counts) / FULL_SCALE
+    }
+}
+
+/// Put the pin behind a channel into analog mode
+///
+/// Channels 0 to 7 are on PA0 to PA7, 8 and 9 on PB0 and PB1, and 10 to 15 on PC0 to PC5. The
+/// internal channels above that have no pin.
+fn set_analog(channel: u8) {
+    let analog = |pin: u8| 0b11_u32 << (2 * u32::from(pin));
+    match channel {
+        0..=7 => {
+            let gpioa = unsafe { &(*GPIOA::ptr()) };
+            gpioa
+                .moder
+                .modify(|r, w| unsafe { w.bits(r.bits() | analog(channel)) });
+        }
+        8 | 9 => {
+            let gpiob = unsafe { &(*GPIOB::ptr()) };
+            gpiob
+                .moder
+                .modify(|r, w| unsafe { w.bits(r.bits() | analog(channel - 8)) });
+        }
+        10..=15 => {
+            let gpioc = unsafe { &(*GPIOC::ptr()) };
+            gpioc
+                .moder
+                .modify(|r, w| unsafe { w.bits(r.bits() | analog(channel - 10)) });
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,133 @@
+//! Arming supervisor, which decides which setpoints may be commanded
+//!
+//! Everything starts disarmed, and only `Idle` is accepted until the motor is armed.
+
+use crate::{config, fault::Fault, motor::ControlState};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Rejection {
+    Disarmed,
+    Fault(Fault),
+    /// Bus voltage outside the fault limits, in volts
+    BusVoltage(f32),
+    /// A setpoint other than zero is still commanded
+    NotZero,
+    /// The rotor is turning too fast against the requested direction
+    Reversal,
+    /// Detection and identification need the rotor to start at rest
+    Moving,
+}
+
+impl Rejection {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Rejection::Disarmed => "not armed",
+            Rejection::Fault(_) => "fault active",
+            Rejection::BusVoltage(_) => "bus voltage out of range",
+            Rejection::NotZero => "setpoint not zero",
+            Rejection::Reversal => "reversing at speed",
+            Rejection::Moving => "rotor turning",
+        }
+    }
+}
+
+pub struct Arming {
+    armed: bool,
+}
+
+impl Arming {
+    pub const fn new() -> Self {
+        Self { armed: false }
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    /// Arm if nothing is wrong and nothing would start driving straight away
+    pub fn arm(
+        &mut self,
+        control: ControlState,
+        fault: Option<Fault>,
+        bus_voltage: f32,
+    ) -> Result<(), Rejection> {
+        if let Some(fault) = fault {
+            return Err(Rejection::Fault(fault));
+        }
+        if !(config::LIMITS.min_voltage..=config::LIMITS.max_voltage).contains(&bus_voltage) {
+            return Err(Rejection::BusVoltage(bus_voltage));
+        }
+        if !is_zero(control) {
+            return Err(Rejection::NotZero);
+        }
+
+        self.armed = true;
+        Ok(())
+    }
+
+    /// Always allowed, the caller must also return to `Idle`
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+
+    /// Disarm while a fault is active, so that clearing it does not start driving again
+    pub fn update(&mut self, fault: Option<Fault>) {
+        if fault.is_some() {
+            self.disarm();
+        }
+    }
+
+    /// Check a change of commanded setpoint with the rotor turning at `erpm`
+    ///
+    /// `Idle` is always accepted.
+    pub fn check(
+        &self,
+        from: ControlState,
+        to: ControlState,
+        fault: Option<Fault>,
+        erpm: f32,
+    ) -> Result<(), Rejection> {
+        if let ControlState::Idle = to {
+            return Ok(());
+        }
+        if let Some(fault) = fault {
+            return Err(Rejection::Fault(fault));
+        }
+        if !self.armed {
+            return Err(Rejection::Disarmed);
+        }
+
+        match (from, to) {
+            (ControlState::DetectHall, ControlState::DetectHall)
+            | (ControlState::Identify, ControlState::Identify) => Ok(()),
+            (_, ControlState::DetectHall) | (_, ControlState::Identify) if erpm != 0.0 => {
+                Err(Rejection::Moving)
+            }
+            // Current follows the rotor, so reverse current regenerates rather than reversing
+            (_, ControlState::Current(_)) => Ok(()),
+            (_, to) => match to.direction() {
+                Some(reverse) => {
+                    let against = if reverse { erpm } else { -erpm };
+                    if against > config::MOTOR.max_reversal_erpm {
+                        Err(Rejection::Reversal)
+                    } else {
+                        Ok(())
+                    }
+                }
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// Whether a setpoint asks for nothing to be driven
+fn is_zero(control: ControlState) -> bool {
+    match control {
+        ControlState::Duty(value) | ControlState::Speed(value) | ControlState::Current(value) => {
+            value == 0.0
+        }
+        ControlState::Forward | ControlState::Reverse => false,
+        ControlState::DetectHall | ControlState::Identify => false,
+        ControlState::Idle | ControlState::Brake(_) => true,
+    }
+}
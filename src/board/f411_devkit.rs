@@ -0,0 +1,117 @@
+//! STM32F411 devkit, with every gate driven straight from a GPIO and the analog inputs wired as
+//! on a VESC 4.12
+
+use {
+    super::{
+        Board, EthernetSpi1, Hardware, Peripherals, ETHERNET_EXTI_PA15, HALL_EXTI_PC6_PC8,
+        VESC_FET_NTC, VESC_MOTOR_NTC,
+    },
+    crate::{
+        adc::{Channels, Scaling},
+        pwm,
+    },
+    crankshaft::{
+        gate::Direct,
+        motor::{MotorDriver, Phase},
+        ntc::Ntc,
+    },
+    stm32f4xx_hal::{
+        gpio::{
+            gpioa::{PA15, PA3, PA4},
+            gpioc::{PC6, PC7, PC8},
+            gpiod::{PD1, PD14, PD2, PD3, PD4, PD5, PD6},
+            Input, Output, PullUp, PushPull,
+        },
+        prelude::*,
+        rcc::Clocks,
+        stm32::{GPIOD, SYSCFG},
+        time::Hertz,
+    },
+};
+
+pub struct F411Devkit;
+
+impl Board for F411Devkit {
+    type PhaseA = Phase<PD1<Output<PushPull>>, PD2<Output<PushPull>>>;
+    type PhaseB = Phase<PD3<Output<PushPull>>, PD4<Output<PushPull>>>;
+    type PhaseC = Phase<PD5<Output<PushPull>>, PD6<Output<PushPull>>>;
+    type Gate = Direct;
+    type Led = PD14<Output<PushPull>>;
+    type Hall1 = PC6<Input<PullUp>>;
+    type Hall2 = PC7<Input<PullUp>>;
+    type Hall3 = PC8<Input<PullUp>>;
+    type EthernetSpi = EthernetSpi1;
+    type EthernetNcs = PA4<Output<PushPull>>;
+    type EthernetInt = PA15<Input<PullUp>>;
+    type EthernetReset = PA3<Output<PushPull>>;
+
+    const SCALING: Scaling = Scaling {
+        shunt_ohms: 0.001,
+        amplifier_gain: 10.0,
+        voltage_divider: (39.0 + 2.2) / 2.2,
+    };
+
+    const CHANNELS: Channels = Channels {
+        phase_voltage: [0, 1, 2],
+        bus_voltage: 12,
+        phase_current: [10, 11],
+        fet_temperature: 14,
+        motor_temperature: 15,
+    };
+    const FET_NTC: Ntc = VESC_FET_NTC;
+    const MOTOR_NTC: Ntc = VESC_MOTOR_NTC;
+    const HALL_EXTI: u32 = HALL_EXTI_PC6_PC8;
+    const ETHERNET_EXTI: u32 = ETHERNET_EXTI_PA15;
+
+    fn init(peripherals: Peripherals, clocks: Clocks, pwm_hz: Hertz) -> Hardware<Self> {
+        let gpioa = peripherals.gpioa.split();
+        let gpioc = peripherals.gpioc.split();
+        let gpiod = peripherals.gpiod.split();
+
+        let mut led = gpiod.pd14.into_push_pull_output();
+        led.set_high();
+
+        pwm::timebase(peripherals.tim1, clocks, pwm_hz);
+
+        let a = Phase::new(
+            gpiod.pd1.into_push_pull_output(),
+            gpiod.pd2.into_push_pull_output(),
+        );
+        let b = Phase::new(
+            gpiod.pd3.into_push_pull_output(),
+            gpiod.pd4.into_push_pull_output(),
+        );
+        let c = Phase::new(
+            gpiod.pd5.into_push_pull_output(),
+            gpiod.pd6.into_push_pull_output(),
+        );
+
+        Hardware {
+            driver: MotorDriver::new(a, b, c),
+            gate: Direct,
+            led,
+            hall: super::hall_pc6_pc8(gpioc.pc6, gpioc.pc7, gpioc.pc8),
+            ethernet: super::ethernet_spi1(
+                peripherals.spi1,
+                gpioa.pa3,
+                gpioa.pa4,
+                gpioa.pa5,
+                gpioa.pa6,
+                gpioa.pa7,
+                gpioa.pa15,
+                clocks,
+            ),
+        }
+    }
+
+    fn route_exti(syscfg: &SYSCFG) {
+        super::route_exti_pc6_pc8_pa15(syscfg);
+    }
+
+    fn force_off() {
+        let gpiod = unsafe { &(*GPIOD::ptr()) };
+        gpiod
+            .bsrr
+            .write(|w| unsafe { w.bits(0b11_1111 << (1 + 16)) });
+    }
+}
@@ -0,0 +1,234 @@
+//! Board support, chosen with one of the `board-vesc4`, `board-vesc6` or `board-f411-devkit`
+//! cargo features
+//!
+//! Everything `init` needs to know about the hardware comes through `Board`, so supporting another
+//! board means adding a module here rather than changing the application.
+
+use {
+    crate::adc::{Channels, Scaling},
+    crankshaft::{
+        gate::GateDriver,
+        motor::{MotorDriver, PhaseDriver},
+        ntc::{Divider, Model, Ntc},
+    },
+    embedded_hal::digital::{InputPin, OutputPin},
+    stm32f4xx_hal::{
+        gpio::{
+            gpioa::{PA15, PA3, PA4, PA5, PA6, PA7},
+            gpioc::{PC6, PC7, PC8},
+            Alternate, Floating, Input, Output, PullUp, PushPull, AF5,
+        },
+        prelude::*,
+        rcc::Clocks,
+        spi::Spi,
+        stm32::{GPIOA, GPIOB, GPIOC, GPIOD, SPI1, SPI3, SYSCFG, TIM1},
+        time::Hertz,
+    },
+};
+
+#[cfg(feature = "board-f411-devkit")]
+mod f411_devkit;
+#[cfg(feature = "board-vesc4")]
+mod vesc4;
+#[cfg(feature = "board-vesc6")]
+mod vesc6;
+
+#[cfg(feature = "board-f411-devkit")]
+pub use self::f411_devkit::F411Devkit as Selected;
+#[cfg(feature = "board-vesc4")]
+pub use self::vesc4::Vesc4 as Selected;
+#[cfg(feature = "board-vesc6")]
+pub use self::vesc6::Vesc6 as Selected;
+
+#[cfg(not(any(
+    feature = "board-vesc4",
+    feature = "board-vesc6",
+    feature = "board-f411-devkit"
+)))]
+compile_error!(
+    "no board selected, enable one of the board-vesc4, board-vesc6 or board-f411-devkit features"
+);
+
+#[cfg(any(
+    all(feature = "board-vesc4", feature = "board-vesc6"),
+    all(feature = "board-vesc4", feature = "board-f411-devkit"),
+    all(feature = "board-vesc6", feature = "board-f411-devkit")
+))]
+compile_error!("more than one board selected, build with --no-default-features to change board");
+
+pub trait Board {
+    type PhaseA: PhaseDriver;
+    type PhaseB: PhaseDriver;
+    type PhaseC: PhaseDriver;
+    type Gate: GateDriver;
+    /// Status LED
+    type Led: OutputPin;
+    /// Hall sensors, on EXTI lines 5 to 9 so `EXTI9_5` serves them
+    type Hall1: InputPin;
+    type Hall2: InputPin;
+    type Hall3: InputPin;
+    /// ENC28J60, with its INT on one of EXTI lines 10 to 15 so `EXTI15_10` serves it
+    type EthernetSpi;
+    type EthernetNcs: OutputPin;
+    type EthernetInt: InputPin;
+    type EthernetReset: OutputPin;
+
+    /// Current shunts, shunt amplifiers and voltage dividers
+    const SCALING: Scaling;
+    const CHANNELS: Channels;
+    /// Thermistor on the power stage, and the one expected in the motor
+    const FET_NTC: Ntc;
+    const MOTOR_NTC: Ntc;
+    /// EXTI lines of the hall sensors, and of the ENC28J60's INT
+    const HALL_EXTI: u32;
+    const ETHERNET_EXTI: u32;
+
+    /// Claim the board's pins, leaving every phase floating, the gate driver disabled and the LED
+    /// off
+    ///
+    /// TIM1 is started at `pwm_hz` however the gates are driven, as it triggers the ADC and paces
+    /// `TIM1_UP_TIM10`.
+    fn init(peripherals: Peripherals, clocks: Clocks, pwm_hz: Hertz) -> Hardware<Self>
+    where
+        Self: Sized;
+
+    /// Connect `HALL_EXTI` and `ETHERNET_EXTI` to the ports their pins are on
+    ///
+    /// SYSCFG must already be clocked.
+    fn route_exti(syscfg: &SYSCFG);
+
+    /// Turn every gate off without the drivers, for when nothing that owns them can be trusted
+    fn force_off();
+}
+
+/// The VESC's FET thermistor, with 10K to ground below it
+const VESC_FET_NTC: Ntc = Ntc {
+    model: Model::Beta {
+        r25: 10_000.0,
+        beta: 3_380.0,
+    },
+    divider: Divider::HighSide,
+    resistor: 10_000.0,
+};
+
+/// The VESC's motor thermistor input, pulled up through 10K
+const VESC_MOTOR_NTC: Ntc = Ntc {
+    model: Model::Beta {
+        r25: 10_000.0,
+        beta: 3_950.0,
+    },
+    divider: Divider::LowSide,
+    resistor: 10_000.0,
+};
+
+/// Peripherals a board may claim, the rest stay with the application
+pub struct Peripherals {
+    pub gpioa: GPIOA,
+    pub gpiob: GPIOB,
+    pub gpioc: GPIOC,
+    pub gpiod: GPIOD,
+    pub tim1: TIM1,
+    pub spi1: SPI1,
+    pub spi3: SPI3,
+}
+
+pub type Driver<B> = MotorDriver<<B as Board>::PhaseA, <B as Board>::PhaseB, <B as Board>::PhaseC>;
+
+pub struct Hardware<B: Board> {
+    pub driver: Driver<B>,
+    pub gate: B::Gate,
+    pub led: B::Led,
+    pub hall: (B::Hall1, B::Hall2, B::Hall3),
+    pub ethernet: EthernetPins<B::EthernetSpi, B::EthernetNcs, B::EthernetInt, B::EthernetReset>,
+}
+
+pub struct EthernetPins<SPI, NCS, INT, RESET> {
+    pub spi: SPI,
+    pub ncs: NCS,
+    /// Low while a received packet is waiting
+    pub int: INT,
+    pub rst: RESET,
+}
+
+/// Hall sensors on PC6 to PC8, as wired on every board so far
+type HallPc6Pc8 = (PC6<Input<PullUp>>, PC7<Input<PullUp>>, PC8<Input<PullUp>>);
+/// EXTI lines 6, 7 and 8
+const HALL_EXTI_PC6_PC8: u32 = 0b111 << 6;
+
+/// ENC28J60 on SPI1 (PA5 to PA7), with chip select on PA4, INT on PA15 and reset on PA3, as
+/// wired on every board so far
+type EthernetSpi1 = Spi<
+    SPI1,
+    (
+        PA5<Alternate<AF5>>,
+        PA6<Alternate<AF5>>,
+        PA7<Alternate<AF5>>,
+    ),
+>;
+type EthernetSpi1Pins =
+    EthernetPins<EthernetSpi1, PA4<Output<PushPull>>, PA15<Input<PullUp>>, PA3<Output<PushPull>>>;
+/// EXTI line 15
+const ETHERNET_EXTI_PA15: u32 = 1 << 15;
+const ETHERNET_SPI_HZ: u32 = 1_000_000;
+
+fn hall_pc6_pc8(
+    pc6: PC6<Input<Floating>>,
+    pc7: PC7<Input<Floating>>,
+    pc8: PC8<Input<Floating>>,
+) -> HallPc6Pc8 {
+    (
+        pc6.into_pull_up_input(),
+        pc7.into_pull_up_input(),
+        pc8.into_pull_up_input(),
+    )
+}
+
+/// The ENC28J60 is held deselected and out of reset
+#[allow(clippy::too_many_arguments)]
+fn ethernet_spi1(
+    spi1: SPI1,
+    pa3: PA3<Input<Floating>>,
+    pa4: PA4<Input<Floating>>,
+    pa5: PA5<Input<Floating>>,
+    pa6: PA6<Input<Floating>>,
+    pa7: PA7<Input<Floating>>,
+    pa15: PA15<Input<Floating>>,
+    clocks: Clocks,
+) -> EthernetSpi1Pins {
+    let mut rst = pa3.into_push_pull_output();
+    rst.set_high();
+    let mut ncs = pa4.into_push_pull_output();
+    ncs.set_high();
+
+    let spi = Spi::spi1(
+        spi1,
+        (
+            pa5.into_alternate_af5(),
+            pa6.into_alternate_af5(),
+            pa7.into_alternate_af5(),
+        ),
+        enc28j60::MODE,
+        ETHERNET_SPI_HZ.hz(),
+        clocks,
+    );
+
+    EthernetPins {
+        spi,
+        ncs,
+        int: pa15.into_pull_up_input(),
+        rst,
+    }
+}
+
+/// Route EXTI 6 to 8 to port C and EXTI 15 to port A
+fn route_exti_pc6_pc8_pa15(syscfg: &SYSCFG) {
+    syscfg
+        .exticr2
+        .modify(|_, w| unsafe { w.exti6().bits(0b0010).exti7().bits(0b0010) });
+    syscfg
+        .exticr3
+        .modify(|_, w| unsafe { w.exti8().bits(0b0010) });
+    syscfg
+        .exticr4
+        .modify(|_, w| unsafe { w.exti15().bits(0b0000) });
+}
@@ -0,0 +1,118 @@
+//! VESC 4.12, with a DRV8302 strapped for six-input PWM and 10x shunt amplifier gain
+
+use {
+    super::{
+        Board, EthernetSpi1, Hardware, Peripherals, ETHERNET_EXTI_PA15, HALL_EXTI_PC6_PC8,
+        VESC_FET_NTC, VESC_MOTOR_NTC,
+    },
+    crate::{
+        adc::{Channels, Scaling},
+        pwm::{self, PwmPhase, C1, C2, C3},
+    },
+    crankshaft::{gate::Drv8302, motor::MotorDriver, ntc::Ntc},
+    stm32f4xx_hal::{
+        gpio::{
+            gpioa::{PA15, PA3, PA4},
+            gpiob::PB0,
+            gpioc::{PC10, PC11, PC12, PC6, PC7, PC8},
+            Input, Output, PullUp, PushPull,
+        },
+        prelude::*,
+        rcc::Clocks,
+        stm32::{GPIOC, SYSCFG},
+        time::Hertz,
+    },
+};
+
+const DEAD_TIME_NS: u32 = 400;
+
+pub struct Vesc4;
+
+impl Board for Vesc4 {
+    type PhaseA = PwmPhase<C1>;
+    type PhaseB = PwmPhase<C2>;
+    type PhaseC = PwmPhase<C3>;
+    type Gate = Drv8302<PC10<Output<PushPull>>, PC11<Input<PullUp>>, PC12<Input<PullUp>>>;
+    type Led = PB0<Output<PushPull>>;
+    type Hall1 = PC6<Input<PullUp>>;
+    type Hall2 = PC7<Input<PullUp>>;
+    type Hall3 = PC8<Input<PullUp>>;
+    type EthernetSpi = EthernetSpi1;
+    type EthernetNcs = PA4<Output<PushPull>>;
+    type EthernetInt = PA15<Input<PullUp>>;
+    type EthernetReset = PA3<Output<PushPull>>;
+
+    const SCALING: Scaling = Scaling {
+        shunt_ohms: 0.001,
+        amplifier_gain: 10.0,
+        voltage_divider: (39.0 + 2.2) / 2.2,
+    };
+
+    /// Phase dividers on PA0 to PA2, current shunts on PC0 and PC1, bus divider on PC2 and
+    /// thermistors on PC4 and PC5
+    const CHANNELS: Channels = Channels {
+        phase_voltage: [0, 1, 2],
+        bus_voltage: 12,
+        phase_current: [10, 11],
+        fet_temperature: 14,
+        motor_temperature: 15,
+    };
+    const FET_NTC: Ntc = VESC_FET_NTC;
+    const MOTOR_NTC: Ntc = VESC_MOTOR_NTC;
+    const HALL_EXTI: u32 = HALL_EXTI_PC6_PC8;
+    const ETHERNET_EXTI: u32 = ETHERNET_EXTI_PA15;
+
+    fn init(peripherals: Peripherals, clocks: Clocks, pwm_hz: Hertz) -> Hardware<Self> {
+        let gpioa = peripherals.gpioa.split();
+        let gpiob = peripherals.gpiob.split();
+        let gpioc = peripherals.gpioc.split();
+
+        let mut led = gpiob.pb0.into_push_pull_output();
+        led.set_low();
+
+        let pins = (
+            gpioa.pa8.into_alternate_af1(),
+            gpioa.pa9.into_alternate_af1(),
+            gpioa.pa10.into_alternate_af1(),
+            gpiob.pb13.into_alternate_af1(),
+            gpiob.pb14.into_alternate_af1(),
+            gpiob.pb15.into_alternate_af1(),
+        );
+        let (a, b, c) = pwm::tim1(peripherals.tim1, pins, clocks, pwm_hz, DEAD_TIME_NS);
+
+        let gate = Drv8302::new(
+            gpioc.pc10.into_push_pull_output(),
+            gpioc.pc11.into_pull_up_input(),
+            gpioc.pc12.into_pull_up_input(),
+        );
+
+        Hardware {
+            driver: MotorDriver::new(a, b, c),
+            gate,
+            led,
+            hall: super::hall_pc6_pc8(gpioc.pc6, gpioc.pc7, gpioc.pc8),
+            ethernet: super::ethernet_spi1(
+                peripherals.spi1,
+                gpioa.pa3,
+                gpioa.pa4,
+                gpioa.pa5,
+                gpioa.pa6,
+                gpioa.pa7,
+                gpioa.pa15,
+                clocks,
+            ),
+        }
+    }
+
+    fn route_exti(syscfg: &SYSCFG) {
+        super::route_exti_pc6_pc8_pa15(syscfg);
+    }
+
+    fn force_off() {
+        pwm::force_off();
+
+        // EN_GATE on PC10
+        let gpioc = unsafe { &(*GPIOC::ptr()) };
+        gpioc.bsrr.write(|w| unsafe { w.bits(1 << (10 + 16)) });
+    }
+}
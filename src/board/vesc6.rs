@@ -0,0 +1,158 @@
+//! VESC 6, with a DRV8301 configured over SPI3 from `config::DRV8301`
+
+use {
+    super::{
+        Board, EthernetSpi1, Hardware, Peripherals, ETHERNET_EXTI_PA15, HALL_EXTI_PC6_PC8,
+        VESC_FET_NTC, VESC_MOTOR_NTC,
+    },
+    crate::{
+        adc::{Channels, Scaling},
+        pwm::{self, PwmPhase, C1, C2, C3},
+    },
+    crankshaft::{
+        config,
+        gate::{Bytes, Drv8301},
+        motor::MotorDriver,
+        ntc::Ntc,
+    },
+    embedded_hal::spi::MODE_1,
+    stm32f4xx_hal::{
+        gpio::{
+            gpioa::{PA15, PA3, PA4},
+            gpiob::{PB0, PB5, PB6, PB7},
+            gpioc::{PC10, PC11, PC12, PC6, PC7, PC8, PC9},
+            Alternate, Input, Output, PullUp, PushPull, AF6,
+        },
+        prelude::*,
+        rcc::Clocks,
+        spi::Spi,
+        stm32::{GPIOB, SPI3, SYSCFG},
+        time::Hertz,
+    },
+};
+
+const DEAD_TIME_NS: u32 = 400;
+const SPI_HZ: u32 = 1_000_000;
+
+type Spi3 = Spi<
+    SPI3,
+    (
+        PC10<Alternate<AF6>>,
+        PC11<Alternate<AF6>>,
+        PC12<Alternate<AF6>>,
+    ),
+>;
+
+pub struct Vesc6;
+
+impl Board for Vesc6 {
+    type PhaseA = PwmPhase<C1>;
+    type PhaseB = PwmPhase<C2>;
+    type PhaseC = PwmPhase<C3>;
+    type Gate = Drv8301<
+        Bytes<Spi3>,
+        PC9<Output<PushPull>>,
+        PB5<Output<PushPull>>,
+        PB7<Input<PullUp>>,
+        PB6<Input<PullUp>>,
+    >;
+    type Led = PB0<Output<PushPull>>;
+    type Hall1 = PC6<Input<PullUp>>;
+    type Hall2 = PC7<Input<PullUp>>;
+    type Hall3 = PC8<Input<PullUp>>;
+    type EthernetSpi = EthernetSpi1;
+    type EthernetNcs = PA4<Output<PushPull>>;
+    type EthernetInt = PA15<Input<PullUp>>;
+    type EthernetReset = PA3<Output<PushPull>>;
+
+    /// The amplifier gain must match `config::DRV8301.gain`
+    const SCALING: Scaling = Scaling {
+        shunt_ohms: 0.0005,
+        amplifier_gain: 20.0,
+        voltage_divider: (39.0 + 2.2) / 2.2,
+    };
+
+    /// Phase dividers on PA0 to PA2, current shunts on PC0 and PC1, bus divider on PC3 and
+    /// thermistors on PC4 and PC5
+    ///
+    /// The third shunt, on PC2, is not used.
+    const CHANNELS: Channels = Channels {
+        phase_voltage: [0, 1, 2],
+        bus_voltage: 13,
+        phase_current: [10, 11],
+        fet_temperature: 14,
+        motor_temperature: 15,
+    };
+    const FET_NTC: Ntc = VESC_FET_NTC;
+    const MOTOR_NTC: Ntc = VESC_MOTOR_NTC;
+    const HALL_EXTI: u32 = HALL_EXTI_PC6_PC8;
+    const ETHERNET_EXTI: u32 = ETHERNET_EXTI_PA15;
+
+    fn init(peripherals: Peripherals, clocks: Clocks, pwm_hz: Hertz) -> Hardware<Self> {
+        let gpioa = peripherals.gpioa.split();
+        let gpiob = peripherals.gpiob.split();
+        let gpioc = peripherals.gpioc.split();
+
+        let mut led = gpiob.pb0.into_push_pull_output();
+        led.set_low();
+
+        let pins = (
+            gpioa.pa8.into_alternate_af1(),
+            gpioa.pa9.into_alternate_af1(),
+            gpioa.pa10.into_alternate_af1(),
+            gpiob.pb13.into_alternate_af1(),
+            gpiob.pb14.into_alternate_af1(),
+            gpiob.pb15.into_alternate_af1(),
+        );
+        let (a, b, c) = pwm::tim1(peripherals.tim1, pins, clocks, pwm_hz, DEAD_TIME_NS);
+
+        let spi = Spi::spi3(
+            peripherals.spi3,
+            (
+                gpioc.pc10.into_alternate_af6(),
+                gpioc.pc11.into_alternate_af6(),
+                gpioc.pc12.into_alternate_af6(),
+            ),
+            MODE_1,
+            SPI_HZ.hz(),
+            clocks,
+        );
+        let gate = Drv8301::new(
+            Bytes(spi),
+            gpioc.pc9.into_push_pull_output(),
+            gpiob.pb5.into_push_pull_output(),
+            gpiob.pb7.into_pull_up_input(),
+            gpiob.pb6.into_pull_up_input(),
+            config::DRV8301,
+        );
+
+        Hardware {
+            driver: MotorDriver::new(a, b, c),
+            gate,
+            led,
+            hall: super::hall_pc6_pc8(gpioc.pc6, gpioc.pc7, gpioc.pc8),
+            ethernet: super::ethernet_spi1(
+                peripherals.spi1,
+                gpioa.pa3,
+                gpioa.pa4,
+                gpioa.pa5,
+                gpioa.pa6,
+                gpioa.pa7,
+                gpioa.pa15,
+                clocks,
+            ),
+        }
+    }
+
+    fn route_exti(syscfg: &SYSCFG) {
+        super::route_exti_pc6_pc8_pa15(syscfg);
+    }
+
+    fn force_off() {
+        pwm::force_off();
+
+        // EN_GATE on PB5
+        let gpiob = unsafe { &(*GPIOB::ptr()) };
+        gpiob.bsrr.write(|w| unsafe { w.bits(1 << (5 + 16)) });
+    }
+}
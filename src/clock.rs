@@ -0,0 +1,59 @@
+//! Milliseconds since boot, counted by TIM2 and extended past its 49 days by counting overflows
+
+use {
+    core::sync::atomic::{AtomicU32, Ordering},
+    stm32f4xx_hal::{
+        rcc::Clocks,
+        stm32::{RCC, TIM2},
+    },
+};
+
+const TICK_HZ: u32 = 1_000;
+
+/// Times the 32-bit counter has wrapped
+static OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+
+/// Start counting from zero, raising TIM2's interrupt whenever the counter wraps
+pub fn start(tim: TIM2, clocks: Clocks) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.apb1enr.modify(|_, w| w.tim2en().set_bit());
+    rcc.apb1rstr.modify(|_, w| w.tim2rst().set_bit());
+    rcc.apb1rstr.modify(|_, w| w.tim2rst().clear_bit());
+
+    // APB1 timers run at twice the bus clock whenever the bus is divided down
+    let tim_clk = match clocks.ppre1() {
+        1 => clocks.pclk1().0,
+        _ => clocks.pclk1().0 * 2,
+    };
+    tim.psc
+        .write(|w| unsafe { w.psc().bits((tim_clk / TICK_HZ - 1) as u16) });
+    tim.arr.write(|w| unsafe { w.bits(u32::max_value()) });
+
+    // Load the prescaler, without counting the update event that does so as an overflow
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.sr.modify(|_, w| w.uif().clear_bit());
+    tim.dier.write(|w| w.uie().set_bit());
+    tim.cr1.write(|w| w.cen().set_bit());
+}
+
+/// Count a wrap of the counter, from TIM2's interrupt
+pub fn overflow() {
+    let tim = unsafe { &(*TIM2::ptr()) };
+    tim.sr.modify(|_, w| w.uif().clear_bit());
+    OVERFLOWS.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Milliseconds since `start`
+///
+/// TIM2's interrupt has to be able to preempt the caller, or a wrap is missed until it returns.
+pub fn now() -> u64 {
+    let tim = unsafe { &(*TIM2::ptr()) };
+    // Read again if the counter wrapped in between
+    loop {
+        let overflows = OVERFLOWS.load(Ordering::Acquire);
+        let count = tim.cnt.read().bits();
+        if OVERFLOWS.load(Ordering::Acquire) == overflows {
+            return u64::from(overflows) << 32 | u64::from(count);
+        }
+    }
+}
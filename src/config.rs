@@ -0,0 +1,137 @@
+use crate::{
+    deadman::Timeout,
+    fault::Limits,
+    gate::{Drv8301Config, Gain, GateCurrent, OcpMode, PwmMode},
+    motor::{BrakeMode, Commutation, ControlState},
+    ntc::Derating,
+    pid::clamp,
+    ramp::Rates,
+};
+
+/// How the motor is driven
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    /// Trapezoidal drive through `CommutationState`
+    SixStep(Commutation),
+    /// Field-oriented control, with the rotor angle from the hall sensors
+    Foc,
+}
+
+pub struct MotorConfig {
+    pub control: Control,
+    pub pole_pairs: u32,
+    /// What `ControlState::Forward` drives, `Reverse` being the same in the other direction
+    ///
+    /// `Duty` only has an effect under six-step, FOC needs `Current` or `Speed`.
+    pub forward: ControlState,
+    /// Slew rate limits applied to every setpoint
+    pub ramp: Rates,
+    /// Held while waiting for the rotor to stop before reversing
+    pub reversal_brake: BrakeMode,
+    /// Fastest the rotor may turn against a newly commanded direction, in eRPM
+    pub max_reversal_erpm: f32,
+    /// Current loop gains, in volts per amp and volts per amp-second
+    pub foc_kp: f32,
+    pub foc_ki: f32,
+    /// Largest voltage the current loop may request, in volts
+    pub foc_max_voltage: f32,
+    /// Bandwidth the current loop gains are derived for once the motor has been identified, in
+    /// radians per second
+    pub foc_bandwidth: f32,
+    /// Speed loop gains, from eRPM to duty cycle under six-step or to amps under FOC
+    pub speed_kp: f32,
+    pub speed_ki: f32,
+    pub speed_kd: f32,
+    pub speed_integral_limit: f32,
+    /// Speed loop output range, in the direction of the setpoint
+    ///
+    /// A negative minimum lets FOC brake to slow down, six-step can only drive.
+    pub speed_output_min: f32,
+    pub speed_output_max: f32,
+    /// Largest current `ControlState::Current` may drive with, in amps
+    pub max_current: f32,
+    /// Largest current `ControlState::Current` may regenerate with, in amps
+    pub max_regen_current: f32,
+    /// Six-step current loop gains, in duty cycle per amp and per amp-second
+    pub current_kp: f32,
+    pub current_ki: f32,
+}
+
+impl MotorConfig {
+    /// Current for a throttle position from -1.0, full regenerative braking, to 1.0
+    pub fn throttle(&self, position: f32) -> f32 {
+        if position < 0.0 {
+            clamp(position, -1.0, 0.0) * self.max_regen_current
+        } else {
+            clamp(position, 0.0, 1.0) * self.max_current
+        }
+    }
+}
+
+pub const MOTOR: MotorConfig = MotorConfig {
+    control: Control::SixStep(Commutation::Hall),
+    pole_pairs: 7,
+    forward: ControlState::Duty(0.2),
+    ramp: Rates {
+        duty: 0.5,
+        current: 50.0,
+        speed: 5_000.0,
+    },
+    reversal_brake: BrakeMode::Dynamic(0.5),
+    max_reversal_erpm: 1_000.0,
+    foc_kp: 0.05,
+    foc_ki: 50.0,
+    foc_max_voltage: 60.0,
+    foc_bandwidth: 2_000.0,
+    speed_kp: 0.000_1,
+    speed_ki: 0.000_5,
+    speed_kd: 0.0,
+    speed_integral_limit: 0.9,
+    speed_output_min: 0.0,
+    speed_output_max: 0.9,
+    max_current: 30.0,
+    max_regen_current: 10.0,
+    current_kp: 0.002,
+    current_ki: 5.0,
+};
+
+pub const LIMITS: Limits = Limits {
+    max_current: 60.0,
+    max_voltage: 57.0,
+    min_voltage: 8.0,
+    max_fet_temperature: 85.0,
+    max_motor_temperature: Some(100.0),
+};
+
+/// Current is reduced ahead of the hard limits in `LIMITS`, so the vehicle slows rather than
+/// cutting out
+pub const FET_DERATING: Derating = Derating {
+    start: 70.0,
+    end: 85.0,
+};
+
+pub const MOTOR_DERATING: Derating = Derating {
+    start: 80.0,
+    end: 100.0,
+};
+
+/// Direction and speed requests stop with the dynamic brake if they are not repeated
+pub const COMMAND_TIMEOUT: Timeout = Timeout {
+    seconds: Some(1.0),
+    fallback: ControlState::Brake(BrakeMode::Dynamic(0.5)),
+};
+
+/// A lost throttle coasts, as if it had been released
+pub const THROTTLE_TIMEOUT: Timeout = Timeout {
+    seconds: Some(0.5),
+    fallback: ControlState::Idle,
+};
+
+/// For boards with a DRV8301, whose shunt amplifier gain must match the board's `Scaling`
+pub const DRV8301: Drv8301Config = Drv8301Config {
+    gate_current: GateCurrent::Amps1_7,
+    pwm_mode: PwmMode::Six,
+    ocp_mode: OcpMode::Latch,
+    oc_adj: 16,
+    gain: Gain::X20,
+};
@@ -0,0 +1,71 @@
+//! Record of a panic, left where it survives the reset that follows so it can be reported after
+
+use core::{
+    cmp,
+    fmt::{self, Write},
+};
+
+/// Marks a record as written by the panic handler rather than left over RAM contents
+const MAGIC: u32 = 0x7061_6e63;
+const MESSAGE_LEN: usize = 128;
+
+/// Panic message and location, truncated to fit
+#[derive(Clone, Copy)]
+pub struct Record {
+    magic: u32,
+    len: usize,
+    message: [u8; MESSAGE_LEN],
+}
+
+impl Record {
+    pub const fn new() -> Self {
+        Self {
+            magic: 0,
+            len: 0,
+            message: [0; MESSAGE_LEN],
+        }
+    }
+
+    /// Start a new record, which is written with `write!` and only valid once `finish`ed
+    pub fn begin(&mut self) {
+        self.magic = 0;
+        self.len = 0;
+    }
+
+    pub fn finish(&mut self) {
+        self.magic = MAGIC;
+    }
+
+    /// A copy of the record if a panic left one
+    ///
+    /// It is cleared so that it is only reported once, unless `head_only` says the response
+    /// carrying it will not have a body.
+    pub fn report(&mut self, head_only: bool) -> Option<Record> {
+        if self.magic != MAGIC || self.len > MESSAGE_LEN {
+            return None;
+        }
+
+        let record = *self;
+        if !head_only {
+            self.magic = 0;
+        }
+        Some(record)
+    }
+
+    pub fn message(&self) -> &str {
+        core::str::from_utf8(&self.message[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Record {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut end = cmp::min(s.len(), MESSAGE_LEN - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        self.message[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+//! Six-step current control
+//!
+//! Regulates the current through the phase driven high by adjusting the duty cycle. With
+//! complementary switching, a duty cycle below the back-EMF lets current flow back into the
+//! supply, so negative targets brake regeneratively without changing commutation direction.
+
+use crate::{motor::CommutationState, pid::Pid, Samples};
+
+pub struct CurrentLoop {
+    pub pid: Pid,
+    /// Commutation direction, as passed to `MotorDriver::step`
+    direction: bool,
+    /// Target in amps, positive when driving in `direction`, `None` while stopped
+    target: Option<f32>,
+}
+
+impl CurrentLoop {
+    pub const fn new(pid: Pid) -> Self {
+        Self {
+            pid,
+            direction: false,
+            target: None,
+        }
+    }
+
+    /// Commutation direction while running
+    pub fn direction(&self) -> Option<bool> {
+        self.target.map(|_| self.direction)
+    }
+
+    /// Start regulating, or change the target while running
+    pub fn set(&mut self, direction: bool, amps: f32) {
+        if self.direction() != Some(direction) {
+            self.pid.reset();
+        }
+
+        self.direction = direction;
+        self.target = Some(amps);
+    }
+
+    pub fn stop(&mut self) {
+        self.target = None;
+    }
+
+    /// Run the loop for one PWM period of `dt` seconds, returning the duty cycle to apply
+    pub fn update(&mut self, samples: &Samples, state: CommutationState, dt: f32) -> Option<f32> {
+        let target = self.target?;
+        let measured = samples.phase_current[state.phases().0];
+
+        Some(self.pid.update(target, measured, dt))
+    }
+}
@@ -0,0 +1,89 @@
+//! Command timeout, which stops the motor when whoever is driving it goes quiet
+
+use crate::motor::ControlState;
+
+/// Where a setpoint came from, each with its own timeout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    /// Direction, speed, current and brake requests
+    Command,
+    /// Throttle position, which is expected to be sent continuously
+    Throttle,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Timeout {
+    /// Seconds without a request before `fallback` is applied, `None` to never time out
+    pub seconds: Option<f32>,
+    /// Applied on timeout, `Idle` or a brake
+    pub fallback: ControlState,
+}
+
+pub struct Deadman {
+    command: Timeout,
+    throttle: Timeout,
+    /// Source of the setpoint being supervised, if any
+    source: Option<Source>,
+    /// Milliseconds since boot when that source was last heard from
+    heard: u64,
+}
+
+impl Deadman {
+    pub const fn new(command: Timeout, throttle: Timeout) -> Self {
+        Self {
+            command,
+            throttle,
+            source: None,
+            heard: 0,
+        }
+    }
+
+    pub fn timeout(&self, source: Source) -> Timeout {
+        match source {
+            Source::Command => self.command,
+            Source::Throttle => self.throttle,
+        }
+    }
+
+    pub fn set_timeout(&mut self, source: Source, seconds: Option<f32>) {
+        match source {
+            Source::Command => self.command.seconds = seconds,
+            Source::Throttle => self.throttle.seconds = seconds,
+        }
+    }
+
+    /// Start or continue supervising a setpoint from `source`, heard from at `now`
+    pub fn refresh(&mut self, source: Source, now: u64) {
+        self.source = Some(source);
+        self.heard = now;
+    }
+
+    /// Stop supervising, for setpoints that are safe to hold indefinitely
+    pub fn release(&mut self) {
+        self.source = None;
+    }
+
+    /// Seconds left at `now` before the supervised setpoint times out
+    pub fn remaining(&self, now: u64) -> Option<f32> {
+        let seconds = self.timeout(self.source?).seconds?;
+        Some(seconds - self.elapsed(now))
+    }
+
+    /// Check the time at `now`, returning the state to fall back to once the source has timed out
+    pub fn update(&mut self, now: u64) -> Option<ControlState> {
+        let timeout = self.timeout(self.source?);
+        let seconds = timeout.seconds?;
+
+        if self.elapsed(now) < seconds {
+            return None;
+        }
+
+        self.source = None;
+        Some(timeout.fallback)
+    }
+
+    /// Seconds since the source was last heard from
+    fn elapsed(&self, now: u64) -> f32 {
+        now.saturating_sub(self.heard) as f32 / 1000.0
+    }
+}
@@ -0,0 +1,157 @@
+//! Latching fault protection
+//!
+//! A fault stays active until it is cleared, and while it is the phases must be left floating.
+
+use crate::{gate::DriverFault, ntc::Temperatures, Samples};
+
+/// Faults remembered after being cleared
+pub const HISTORY: usize = 8;
+/// Consecutive out of range samples needed to latch a voltage fault, so that switching noise on a
+/// single conversion does not trip it
+const VOLTAGE_SAMPLES: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    OverCurrent,
+    OverVoltage,
+    UnderVoltage,
+    OverTempFet,
+    OverTempMotor,
+    /// A thermistor reads open or shorted, so its temperature is unknown
+    Thermistor,
+    /// The last reset was the watchdog's, so the firmware hung with the phases in some state
+    Watchdog,
+    /// Reported by the gate driver, which has already shut the gates off
+    GateDriver(DriverFault),
+}
+
+impl Fault {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Fault::OverCurrent => "phase overcurrent",
+            Fault::OverVoltage => "bus overvoltage",
+            Fault::UnderVoltage => "bus undervoltage",
+            Fault::OverTempFet => "mosfet overtemperature",
+            Fault::OverTempMotor => "motor overtemperature",
+            Fault::Thermistor => "thermistor open or shorted",
+            Fault::Watchdog => "watchdog reset",
+            Fault::GateDriver(fault) => fault.description(),
+        }
+    }
+}
+
+/// Bounds outside which the motor must not be driven
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Largest phase current in either direction, in amps
+    pub max_current: f32,
+    /// Bus voltage range, in volts
+    pub max_voltage: f32,
+    pub min_voltage: f32,
+    /// Thermistor temperatures, in °C
+    pub max_fet_temperature: f32,
+    /// `None` for a motor without a thermistor, whose input is then ignored
+    pub max_motor_temperature: Option<f32>,
+}
+
+pub struct Faults {
+    limits: Limits,
+    active: Option<Fault>,
+    /// Consecutive samples with the bus voltage out of range
+    voltage_samples: u32,
+    history: [Option<Fault>; HISTORY],
+    /// Where the next fault goes in `history`
+    next: usize,
+}
+
+impl Faults {
+    pub const fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            active: None,
+            voltage_samples: 0,
+            history: [None; HISTORY],
+            next: 0,
+        }
+    }
+
+    pub fn active(&self) -> Option<Fault> {
+        self.active
+    }
+
+    /// Check one set of current and voltage samples, returning the active fault
+    pub fn check(&mut self, samples: &Samples) -> Option<Fault> {
+        let limit = self.limits.max_current;
+        if samples
+            .phase_current
+            .iter()
+            .any(|&current| current > limit || current < -limit)
+        {
+            self.latch(Fault::OverCurrent);
+        }
+
+        let voltage = samples.bus_voltage;
+        let fault = if voltage > self.limits.max_voltage {
+            Some(Fault::OverVoltage)
+        } else if voltage < self.limits.min_voltage {
+            Some(Fault::UnderVoltage)
+        } else {
+            None
+        };
+        match fault {
+            Some(fault) => {
+                self.voltage_samples += 1;
+                if self.voltage_samples >= VOLTAGE_SAMPLES {
+                    self.latch(fault);
+                }
+            }
+            None => self.voltage_samples = 0,
+        }
+
+        self.active
+    }
+
+    /// Check the thermistor temperatures, returning the active fault
+    pub fn check_temperatures(&mut self, temperatures: &Temperatures) -> Option<Fault> {
+        match temperatures.fet {
+            Some(fet) if fet > self.limits.max_fet_temperature => self.latch(Fault::OverTempFet),
+            Some(_) => (),
+            None => self.latch(Fault::Thermistor),
+        }
+        match (temperatures.motor, self.limits.max_motor_temperature) {
+            (Some(motor), Some(max)) if motor > max => self.latch(Fault::OverTempMotor),
+            (None, Some(_)) => self.latch(Fault::Thermistor),
+            _ => (),
+        }
+
+        self.active
+    }
+
+    /// Clear the active fault, which latches again on the next check if its cause remains
+    pub fn clear(&mut self) {
+        self.active = None;
+        self.voltage_samples = 0;
+    }
+
+    /// The most recently latched faults, oldest first
+    pub fn history(&self) -> [Option<Fault>; HISTORY] {
+        let mut history = [None; HISTORY];
+        for (i, fault) in history.iter_mut().enumerate() {
+            *fault = self.history[(self.next + i) % HISTORY];
+        }
+        history
+    }
+
+    /// Latch a fault found outside these checks
+    ///
+    /// Only the first fault is kept active, later ones are usually consequences of it.
+    pub fn latch(&mut self, fault: Fault) {
+        if self.active.is_some() {
+            return;
+        }
+
+        self.active = Some(fault);
+        self.history[self.next] = Some(fault);
+        self.next = (self.next + 1) % HISTORY;
+    }
+}
@@ -0,0 +1,171 @@
+//! Persistent storage in the last flash sector
+//!
+//! The sector is left out of the MCU's layout in `memory/` so that it is never programmed with
+//! firmware. It holds a single record of words, which is replaced by erasing the whole sector.
+
+use {core::ptr, stm32f4xx_hal::stm32::FLASH};
+
+/// Sector 11, the last 128K of the STM32F405's 1M
+#[cfg(feature = "stm32f405")]
+const SECTOR: u8 = 11;
+#[cfg(feature = "stm32f405")]
+const ADDRESS: usize = 0x080e_0000;
+/// Sector 7, the last 128K of the STM32F411's 512K
+#[cfg(feature = "stm32f411")]
+const SECTOR: u8 = 7;
+#[cfg(feature = "stm32f411")]
+const ADDRESS: usize = 0x0806_0000;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+/// 32-bit parallelism, which needs a supply of at least 2.7V
+const PSIZE_X32: u8 = 0b10;
+
+/// FLASH_SR flags reporting a failed erase or program, which stay set until written with one
+const PGSERR: u32 = 1 << 7;
+const PGPERR: u32 = 1 << 6;
+const PGAERR: u32 = 1 << 5;
+const WRPERR: u32 = 1 << 4;
+const OPERR: u32 = 1 << 1;
+const ERRORS: u32 = PGSERR | PGPERR | PGAERR | WRPERR | OPERR;
+
+/// Marks the start of a record, change it whenever the layout of the stored words changes
+const MAGIC: u32 = 0x6372_6b02;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Error {
+    /// PGSERR, the erase or program sequence was wrong
+    Sequence,
+    /// PGPERR, the parallelism did not match the access size
+    Parallelism,
+    /// PGAERR, a write crossed a row boundary
+    Alignment,
+    /// WRPERR, the sector is write protected
+    WriteProtected,
+    /// OPERR, the controller reported the operation itself failed
+    Operation,
+    /// Programming finished without error but the word reads back wrong
+    Verify,
+}
+
+impl Error {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Error::Sequence => "flash sequence error",
+            Error::Parallelism => "flash parallelism error",
+            Error::Alignment => "flash alignment error",
+            Error::WriteProtected => "flash write protected",
+            Error::Operation => "flash operation error",
+            Error::Verify => "flash verify failed",
+        }
+    }
+}
+
+pub struct Flash {
+    flash: FLASH,
+}
+
+impl Flash {
+    pub fn new(flash: FLASH) -> Self {
+        Self { flash }
+    }
+
+    /// Read the stored record into `words`, returning false if there is none of that length
+    pub fn read(&self, words: &mut [u32]) -> bool {
+        if self.word(0) != MAGIC || self.word(1) != words.len() as u32 {
+            return false;
+        }
+
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = self.word(2 + i);
+        }
+
+        self.word(2 + words.len()) == checksum(words)
+    }
+
+    /// Replace the stored record with `words`
+    ///
+    /// Erasing stalls every instruction fetch from flash, interrupts included, for up to a couple
+    /// of seconds, so only do this with the motor stopped. On error the sector is left without a
+    /// valid record.
+    pub fn write(&mut self, words: &[u32]) -> Result<(), Error> {
+        self.unlock();
+
+        // Flags left over from an earlier failure stop any further erase or program
+        self.flash.sr.write(|w| unsafe { w.bits(ERRORS) });
+
+        let result = self.erase().and_then(|()| self.program_record(words));
+
+        self.flash
+            .cr
+            .modify(|_, w| w.ser().clear_bit().pg().clear_bit().lock().set_bit());
+        result
+    }
+
+    fn erase(&mut self) -> Result<(), Error> {
+        self.flash
+            .cr
+            .modify(|_, w| unsafe { w.psize().bits(PSIZE_X32).ser().set_bit().snb().bits(SECTOR) });
+        self.flash.cr.modify(|_, w| w.strt().set_bit());
+        self.wait()?;
+        self.flash.cr.modify(|_, w| w.ser().clear_bit());
+        Ok(())
+    }
+
+    fn program_record(&mut self, words: &[u32]) -> Result<(), Error> {
+        self.flash.cr.modify(|_, w| w.pg().set_bit());
+        self.program(0, MAGIC)?;
+        self.program(1, words.len() as u32)?;
+        for (i, &word) in words.iter().enumerate() {
+            self.program(2 + i, word)?;
+        }
+        self.program(2 + words.len(), checksum(words))
+    }
+
+    fn unlock(&mut self) {
+        if self.flash.cr.read().lock().bit_is_set() {
+            self.flash.keyr.write(|w| unsafe { w.bits(KEY1) });
+            self.flash.keyr.write(|w| unsafe { w.bits(KEY2) });
+        }
+    }
+
+    /// Wait for the current operation to finish and report how it went
+    fn wait(&self) -> Result<(), Error> {
+        while self.flash.sr.read().bsy().bit_is_set() {}
+
+        let sr = self.flash.sr.read().bits();
+        if sr & PGSERR != 0 {
+            Err(Error::Sequence)
+        } else if sr & PGPERR != 0 {
+            Err(Error::Parallelism)
+        } else if sr & PGAERR != 0 {
+            Err(Error::Alignment)
+        } else if sr & WRPERR != 0 {
+            Err(Error::WriteProtected)
+        } else if sr & OPERR != 0 {
+            Err(Error::Operation)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn word(&self, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile((ADDRESS as *const u32).add(offset)) }
+    }
+
+    fn program(&mut self, offset: usize, word: u32) -> Result<(), Error> {
+        unsafe { ptr::write_volatile((ADDRESS as *mut u32).add(offset), word) };
+        self.wait()?;
+
+        if self.word(offset) != word {
+            return Err(Error::Verify);
+        }
+        Ok(())
+    }
+}
+
+fn checksum(words: &[u32]) -> u32 {
+    !words
+        .iter()
+        .fold(MAGIC, |sum, &word| sum.rotate_left(1) ^ word)
+}
@@ -0,0 +1,275 @@
+//! Field-oriented control
+//!
+//! Phase currents are transformed into the rotor frame, regulated by a PI controller on each of the
+//! d and q axes, and the resulting voltage is transformed back and space-vector modulated into
+//! three duty cycles. Nothing here touches hardware.
+
+use {
+    crate::{
+        motor::CommutationState,
+        pid::{clamp, Pid},
+    },
+    core::f32::consts::{FRAC_PI_2, FRAC_PI_3, FRAC_PI_6, PI},
+};
+
+const TAU: f32 = 2.0 * PI;
+const SQRT_3: f32 = 1.732_050_8;
+const FRAC_1_SQRT_3: f32 = 0.577_350_26;
+
+/// Largest voltage magnitude, as a fraction of the bus voltage, that SVPWM can produce without
+/// distortion
+const MAX_MODULATION: f32 = FRAC_1_SQRT_3;
+
+/// Quantity in the stationary frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlphaBeta {
+    pub alpha: f32,
+    pub beta: f32,
+}
+
+/// Quantity in the rotor frame
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dq {
+    pub d: f32,
+    pub q: f32,
+}
+
+/// Clarke transform of the phase A and B quantities, assuming all three sum to zero
+pub fn clarke(a: f32, b: f32) -> AlphaBeta {
+    AlphaBeta {
+        alpha: a,
+        beta: (a + 2.0 * b) * FRAC_1_SQRT_3,
+    }
+}
+
+pub fn park(v: AlphaBeta, sin: f32, cos: f32) -> Dq {
+    Dq {
+        d: v.alpha * cos + v.beta * sin,
+        q: v.beta * cos - v.alpha * sin,
+    }
+}
+
+pub fn inverse_park(v: Dq, sin: f32, cos: f32) -> AlphaBeta {
+    AlphaBeta {
+        alpha: v.d * cos - v.q * sin,
+        beta: v.d * sin + v.q * cos,
+    }
+}
+
+/// Duty cycles for phases A to C producing `v`, given as a fraction of the bus voltage
+///
+/// Centres the phase voltages between the rails, which gives the same switching pattern as
+/// classic sector-based space-vector modulation.
+pub fn svpwm(v: AlphaBeta) -> [f32; 3] {
+    let a = v.alpha;
+    let b = -0.5 * v.alpha + 0.5 * SQRT_3 * v.beta;
+    let c = -0.5 * v.alpha - 0.5 * SQRT_3 * v.beta;
+
+    let max = a.max(b).max(c);
+    let min = a.min(b).min(c);
+    let offset = 0.5 - (max + min) / 2.0;
+
+    [
+        clamp(a + offset, 0.0, 1.0),
+        clamp(b + offset, 0.0, 1.0),
+        clamp(c + offset, 0.0, 1.0),
+    ]
+}
+
+/// Sine and cosine of an angle in radians, accurate to about 0.001
+pub fn sin_cos(angle: f32) -> (f32, f32) {
+    let angle = wrap(angle);
+    let cos_angle = if angle > FRAC_PI_2 {
+        angle - 3.0 * FRAC_PI_2
+    } else {
+        angle + FRAC_PI_2
+    };
+
+    (sin(angle), sin(cos_angle))
+}
+
+/// Wrap an angle into -π to π
+fn wrap(mut angle: f32) -> f32 {
+    while angle > PI {
+        angle -= TAU;
+    }
+    while angle < -PI {
+        angle += TAU;
+    }
+    angle
+}
+
+/// Parabolic approximation with one refinement step, valid from -π to π
+fn sin(x: f32) -> f32 {
+    const B: f32 = 4.0 / PI;
+    const C: f32 = -4.0 / (PI * PI);
+    const P: f32 = 0.225;
+
+    let y = B * x + C * x * abs(x);
+    P * (y * abs(y) - y) + y
+}
+
+fn abs(x: f32) -> f32 {
+    if x < 0.0 {
+        -x
+    } else {
+        x
+    }
+}
+
+/// Square root by Newton's method from a bit-level first guess
+fn sqrt(x: f32) -> f32 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+
+    let mut y = f32::from_bits((x.to_bits() >> 1) + 0x1fbd_1df5);
+    y = 0.5 * (y + x / y);
+    0.5 * (y + x / y)
+}
+
+/// Rotor angle from hall sensor edges, interpolated through each sector using the time the
+/// previous one took
+pub struct HallAngle {
+    position: CommutationState,
+    /// Angle at which the rotor entered the current sector
+    edge: f32,
+    /// 1.0 for `CommutationState::next` order, -1.0 for `previous`, 0.0 when unknown
+    direction: f32,
+    /// PWM periods since the last edge
+    elapsed: u32,
+    /// PWM periods the previous sector took
+    period: u32,
+}
+
+impl HallAngle {
+    pub const fn new() -> Self {
+        Self {
+            position: CommutationState::AB,
+            edge: 0.0,
+            direction: 0.0,
+            elapsed: 0,
+            period: 0,
+        }
+    }
+
+    /// Restart from standstill at a known sector
+    pub fn reset(&mut self, position: CommutationState) {
+        *self = Self::new();
+        self.position = position;
+        self.edge = position.angle();
+    }
+
+    pub fn edge(&mut self, position: CommutationState) {
+        let direction = if position == self.position.next() {
+            1.0
+        } else if position == self.position.previous() {
+            -1.0
+        } else {
+            0.0
+        };
+
+        // Only trust the sector time if the rotor kept going the same way through it
+        self.period = if direction != 0.0 && direction == self.direction {
+            self.elapsed
+        } else {
+            0
+        };
+        self.direction = direction;
+        self.elapsed = 0;
+        self.position = position;
+        self.edge = position.angle() - direction * FRAC_PI_6;
+    }
+
+    /// Advance by one PWM period, returning the estimated electrical angle
+    pub fn update(&mut self) -> f32 {
+        self.elapsed = self.elapsed.saturating_add(1);
+
+        if self.period == 0 {
+            // No speed estimate yet, assume the centre of the sector
+            return self.position.angle();
+        }
+
+        let progress = clamp(self.elapsed as f32 / self.period as f32, 0.0, 1.0);
+        self.edge + self.direction * FRAC_PI_3 * progress
+    }
+}
+
+pub struct Foc {
+    pub d: Pid,
+    pub q: Pid,
+    pub angle: HallAngle,
+    /// Most recently measured current
+    pub current: Dq,
+    /// Target current in amps, `None` while stopped
+    target: Option<Dq>,
+}
+
+impl Foc {
+    pub const fn new(d: Pid, q: Pid) -> Self {
+        Self {
+            d,
+            q,
+            angle: HallAngle::new(),
+            current: Dq { d: 0.0, q: 0.0 },
+            target: None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.target.is_some()
+    }
+
+    /// Start regulating the q axis current to `iq` amps, positive in `CommutationState::next`
+    /// order
+    pub fn start(&mut self, position: CommutationState, iq: f32) {
+        self.d.reset();
+        self.q.reset();
+        self.angle.reset(position);
+        self.target = Some(Dq { d: 0.0, q: iq });
+    }
+
+    /// Change the q axis current target while running
+    pub fn set_current(&mut self, iq: f32) {
+        if let Some(target) = self.target.as_mut() {
+            target.q = iq;
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.target = None;
+    }
+
+    /// Run the current loop for one PWM period of `dt` seconds, returning phase duty cycles
+    pub fn update(&mut self, ia: f32, ib: f32, bus_voltage: f32, dt: f32) -> Option<[f32; 3]> {
+        let target = self.target?;
+
+        let (sin, cos) = sin_cos(self.angle.update());
+        let current = park(clarke(ia, ib), sin, cos);
+        self.current = current;
+
+        let voltage = Dq {
+            d: self.d.update(target.d, current.d, dt),
+            q: self.q.update(target.q, current.q, dt),
+        };
+
+        // Normalise to the bus and limit to the linear modulation range
+        let v = inverse_park(voltage, sin, cos);
+        let scale = if bus_voltage > 1.0 {
+            1.0 / bus_voltage
+        } else {
+            0.0
+        };
+        let mut v = AlphaBeta {
+            alpha: v.alpha * scale,
+            beta: v.beta * scale,
+        };
+        let magnitude = sqrt(v.alpha * v.alpha + v.beta * v.beta);
+        if magnitude > MAX_MODULATION {
+            v.alpha *= MAX_MODULATION / magnitude;
+            v.beta *= MAX_MODULATION / magnitude;
+        }
+
+        Some(svpwm(v))
+    }
+}
@@ -0,0 +1,388 @@
+//! Three-phase gate drivers between the PWM outputs and the MOSFETs
+//!
+//! The DRV8301 is configured and reports its faults over SPI, the DRV8302 is configured by
+//! strapping pins and only signals that something is wrong. Both shut the gates off by themselves
+//! on a fault and hold them off until it is reset.
+
+use embedded_hal::{
+    blocking::{delay::DelayUs, spi::Transfer},
+    digital::{InputPin, OutputPin},
+};
+
+/// Time after EN_GATE rises before the driver accepts SPI commands, in microseconds
+const STARTUP_US: u16 = 10_000;
+/// EN_GATE low pulse short enough to reset faults without a full restart, in microseconds
+const RESET_PULSE_US: u16 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fet {
+    HighA,
+    LowA,
+    HighB,
+    LowB,
+    HighC,
+    LowC,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DriverFault {
+    /// nFAULT asserted by a driver that cannot say why
+    Unspecified,
+    PvddUnderVoltage,
+    GvddUnderVoltage,
+    GvddOverVoltage,
+    OverTemperature,
+    /// Drain to source voltage over the OC_ADJ threshold
+    OverCurrent(Fet),
+    /// No sensible reply over SPI
+    Spi,
+    /// Control registers read back different from what was written
+    Config,
+}
+
+impl DriverFault {
+    pub fn description(&self) -> &'static str {
+        match self {
+            DriverFault::Unspecified => "gate driver fault",
+            DriverFault::PvddUnderVoltage => "gate driver supply undervoltage",
+            DriverFault::GvddUnderVoltage => "gate drive undervoltage",
+            DriverFault::GvddOverVoltage => "gate drive overvoltage",
+            DriverFault::OverTemperature => "gate driver overtemperature",
+            DriverFault::OverCurrent(Fet::HighA) => "mosfet overcurrent, high side a",
+            DriverFault::OverCurrent(Fet::LowA) => "mosfet overcurrent, low side a",
+            DriverFault::OverCurrent(Fet::HighB) => "mosfet overcurrent, high side b",
+            DriverFault::OverCurrent(Fet::LowB) => "mosfet overcurrent, low side b",
+            DriverFault::OverCurrent(Fet::HighC) => "mosfet overcurrent, high side c",
+            DriverFault::OverCurrent(Fet::LowC) => "mosfet overcurrent, low side c",
+            DriverFault::Spi => "gate driver not responding",
+            DriverFault::Config => "gate driver configuration not accepted",
+        }
+    }
+}
+
+pub trait GateDriver {
+    /// Configure the driver and enable the gates
+    fn enable<D: DelayUs<u16>>(&mut self, delay: &mut D) -> Result<(), DriverFault>;
+
+    /// Disable the gates, floating every phase whatever the PWM outputs do
+    fn disable(&mut self);
+
+    /// Clear the latched fault, which comes straight back if its cause remains
+    fn reset<D: DelayUs<u16>>(&mut self, delay: &mut D);
+
+    /// Fault the driver is reporting, decoded as far as it allows
+    fn fault(&mut self) -> Option<DriverFault>;
+
+    /// Overcurrent or overtemperature warning, which does not stop the gates
+    fn warning(&self) -> bool;
+}
+
+/// Peak gate drive current
+#[derive(Debug, Clone, Copy)]
+pub enum GateCurrent {
+    Amps1_7 = 0b00,
+    Amps0_7 = 0b01,
+    Amps0_25 = 0b10,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PwmMode {
+    /// Every gate follows its own input, as TIM1's complementary outputs drive them
+    Six = 0,
+    /// Each low side is the complement of its high side input
+    Three = 1,
+}
+
+/// Response to a MOSFET overcurrent
+#[derive(Debug, Clone, Copy)]
+pub enum OcpMode {
+    /// Cut the pulse short and warn on nOCTW
+    CurrentLimit = 0b00,
+    /// Shut down and assert nFAULT until reset
+    Latch = 0b01,
+    ReportOnly = 0b10,
+    Disabled = 0b11,
+}
+
+/// Current shunt amplifier gain
+#[derive(Debug, Clone, Copy)]
+pub enum Gain {
+    X10 = 0b00,
+    X20 = 0b01,
+    X40 = 0b10,
+    X80 = 0b11,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Drv8301Config {
+    pub gate_current: GateCurrent,
+    pub pwm_mode: PwmMode,
+    pub ocp_mode: OcpMode,
+    /// Drain to source overcurrent threshold, from 0 for 0.06V to 31 for 2.4V
+    pub oc_adj: u8,
+    pub gain: Gain,
+}
+
+impl Drv8301Config {
+    fn control1(&self) -> u16 {
+        (self.gate_current as u16)
+            | (self.pwm_mode as u16) << 3
+            | (self.ocp_mode as u16) << 4
+            | u16::from(self.oc_adj & 0x1f) << 6
+    }
+
+    fn control2(&self) -> u16 {
+        // nOCTW reports both overtemperature and overcurrent
+        (self.gain as u16) << 2
+    }
+}
+
+const READ: u16 = 1 << 15;
+/// Set in a reply when the previous frame was malformed
+const FRAME_FAULT: u16 = 1 << 15;
+const DATA: u16 = 0x07ff;
+
+const STATUS1: u8 = 0x0;
+const STATUS2: u8 = 0x1;
+const CONTROL1: u8 = 0x2;
+const CONTROL2: u8 = 0x3;
+
+const GATE_RESET: u16 = 1 << 2;
+
+/// Status register 1 bits, in the order they are reported
+const STATUS1_FAULTS: [(u16, DriverFault); 9] = [
+    (1 << 8, DriverFault::PvddUnderVoltage),
+    (1 << 9, DriverFault::GvddUnderVoltage),
+    (1 << 7, DriverFault::OverTemperature),
+    (1 << 5, DriverFault::OverCurrent(Fet::HighA)),
+    (1 << 4, DriverFault::OverCurrent(Fet::LowA)),
+    (1 << 3, DriverFault::OverCurrent(Fet::HighB)),
+    (1 << 2, DriverFault::OverCurrent(Fet::LowB)),
+    (1 << 1, DriverFault::OverCurrent(Fet::HighC)),
+    (1 << 0, DriverFault::OverCurrent(Fet::LowC)),
+];
+const STATUS2_GVDD_OV: u16 = 1 << 7;
+
+/// DRV8301, on an SPI bus in mode 1 with 16-bit words
+pub struct Drv8301<SPI, CS, EN, FAULT, OCTW> {
+    spi: SPI,
+    cs: CS,
+    en_gate: EN,
+    nfault: FAULT,
+    noctw: OCTW,
+    config: Drv8301Config,
+}
+
+impl<SPI, CS, EN, FAULT, OCTW> Drv8301<SPI, CS, EN, FAULT, OCTW>
+where
+    SPI: Transfer<u16>,
+    CS: OutputPin,
+    EN: OutputPin,
+    FAULT: InputPin,
+    OCTW: InputPin,
+{
+    /// Leaves the gates disabled until `enable`
+    pub fn new(
+        spi: SPI,
+        mut cs: CS,
+        mut en_gate: EN,
+        nfault: FAULT,
+        noctw: OCTW,
+        config: Drv8301Config,
+    ) -> Self {
+        cs.set_high();
+        en_gate.set_low();
+        Self {
+            spi,
+            cs,
+            en_gate,
+            nfault,
+            noctw,
+            config,
+        }
+    }
+
+    /// Read a register
+    ///
+    /// The reply to each frame arrives during the next, so this takes two.
+    pub fn read(&mut self, address: u8) -> Result<u16, DriverFault> {
+        let command = READ | u16::from(address) << 11;
+        self.transfer(command)?;
+        let reply = self.transfer(command)?;
+
+        if reply & FRAME_FAULT != 0 || (reply >> 11) & 0xf != u16::from(address) {
+            return Err(DriverFault::Spi);
+        }
+        Ok(reply & DATA)
+    }
+
+    pub fn write(&mut self, address: u8, data: u16) -> Result<(), DriverFault> {
+        self.transfer(u16::from(address) << 11 | (data & DATA))
+            .map(|_| ())
+    }
+
+    fn transfer(&mut self, word: u16) -> Result<u16, DriverFault> {
+        let mut words = [word];
+        self.cs.set_low();
+        let reply = self.spi.transfer(&mut words).map(|words| words[0]);
+        self.cs.set_high();
+        reply.map_err(|_| DriverFault::Spi)
+    }
+
+    fn configure(&mut self) -> Result<(), DriverFault> {
+        let control1 = self.config.control1();
+        let control2 = self.config.control2();
+        self.write(CONTROL1, control1)?;
+        self.write(CONTROL2, control2)?;
+
+        if self.read(CONTROL1)? != control1 || self.read(CONTROL2)? != control2 {
+            return Err(DriverFault::Config);
+        }
+        Ok(())
+    }
+}
+
+impl<SPI, CS, EN, FAULT, OCTW> GateDriver for Drv8301<SPI, CS, EN, FAULT, OCTW>
+where
+    SPI: Transfer<u16>,
+    CS: OutputPin,
+    EN: OutputPin,
+    FAULT: InputPin,
+    OCTW: InputPin,
+{
+    fn enable<D: DelayUs<u16>>(&mut self, delay: &mut D) -> Result<(), DriverFault> {
+        self.en_gate.set_high();
+        delay.delay_us(STARTUP_US);
+
+        let result = self.configure();
+        if result.is_err() {
+            self.disable();
+        }
+        result
+    }
+
+    fn disable(&mut self) {
+        self.en_gate.set_low();
+    }
+
+    fn reset<D: DelayUs<u16>>(&mut self, _delay: &mut D) {
+        let control1 = self.config.control1();
+        self.write(CONTROL1, control1 | GATE_RESET).ok();
+    }
+
+    fn fault(&mut self) -> Option<DriverFault> {
+        if self.nfault.is_high() {
+            return None;
+        }
+
+        let status1 = match self.read(STATUS1) {
+            Ok(status) => status,
+            Err(fault) => return Some(fault),
+        };
+        if let Some(&(_, fault)) = STATUS1_FAULTS.iter().find(|(bit, _)| status1 & bit != 0) {
+            return Some(fault);
+        }
+
+        match self.read(STATUS2) {
+            Ok(status) if status & STATUS2_GVDD_OV != 0 => Some(DriverFault::GvddOverVoltage),
+            Ok(_) => Some(DriverFault::Unspecified),
+            Err(fault) => Some(fault),
+        }
+    }
+
+    fn warning(&self) -> bool {
+        self.noctw.is_low()
+    }
+}
+
+/// DRV8302, configured by the board's strapping of M_PWM, M_OC, GAIN and OC_ADJ
+pub struct Drv8302<EN, FAULT, OCTW> {
+    en_gate: EN,
+    nfault: FAULT,
+    noctw: OCTW,
+}
+
+impl<EN: OutputPin, FAULT: InputPin, OCTW: InputPin> Drv8302<EN, FAULT, OCTW> {
+    /// Leaves the gates disabled until `enable`
+    pub fn new(mut en_gate: EN, nfault: FAULT, noctw: OCTW) -> Self {
+        en_gate.set_low();
+        Self {
+            en_gate,
+            nfault,
+            noctw,
+        }
+    }
+}
+
+impl<EN: OutputPin, FAULT: InputPin, OCTW: InputPin> GateDriver for Drv8302<EN, FAULT, OCTW> {
+    fn enable<D: DelayUs<u16>>(&mut self, delay: &mut D) -> Result<(), DriverFault> {
+        self.en_gate.set_high();
+        delay.delay_us(STARTUP_US);
+
+        match self.fault() {
+            Some(fault) => Err(fault),
+            None => Ok(()),
+        }
+    }
+
+    fn disable(&mut self) {
+        self.en_gate.set_low();
+    }
+
+    fn reset<D: DelayUs<u16>>(&mut self, delay: &mut D) {
+        self.en_gate.set_low();
+        delay.delay_us(RESET_PULSE_US);
+        self.en_gate.set_high();
+    }
+
+    fn fault(&mut self) -> Option<DriverFault> {
+        if self.nfault.is_low() {
+            Some(DriverFault::Unspecified)
+        } else {
+            None
+        }
+    }
+
+    fn warning(&self) -> bool {
+        self.noctw.is_low()
+    }
+}
+
+/// Gates driven straight from the PWM pins, with no driver to enable or report faults
+pub struct Direct;
+
+impl GateDriver for Direct {
+    fn enable<D: DelayUs<u16>>(&mut self, _delay: &mut D) -> Result<(), DriverFault> {
+        Ok(())
+    }
+
+    fn disable(&mut self) {}
+
+    fn reset<D: DelayUs<u16>>(&mut self, _delay: &mut D) {}
+
+    fn fault(&mut self) -> Option<DriverFault> {
+        None
+    }
+
+    fn warning(&self) -> bool {
+        false
+    }
+}
+
+/// 16-bit words sent as two bytes, most significant first, over an SPI bus set up for 8-bit words
+///
+/// Chip select is held across both bytes, so the other end sees a single 16-bit frame.
+pub struct Bytes<SPI>(pub SPI);
+
+impl<SPI: Transfer<u8>> Transfer<u16> for Bytes<SPI> {
+    type Error = SPI::Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u16]) -> Result<&'w [u16], SPI::Error> {
+        for word in words.iter_mut() {
+            let mut bytes = [(*word >> 8) as u8, *word as u8];
+            let reply = self.0.transfer(&mut bytes)?;
+            *word = u16::from(reply[0]) << 8 | u16::from(reply[1]);
+        }
+        Ok(words)
+    }
+}
@@ -0,0 +1,96 @@
+use {crate::motor::CommutationState, embedded_hal::digital::InputPin};
+
+/// Maps each 3-bit hall code to the commutation state the rotor is aligned with
+///
+/// Codes 0b000 and 0b111 never occur with a healthy sensor and map to `None`.
+#[derive(Debug, Clone, Copy)]
+pub struct HallTable(pub [Option<CommutationState>; 8]);
+
+impl Default for HallTable {
+    /// A common 120° sensor wiring, in the sequence 1, 3, 2, 6, 4, 5
+    fn default() -> Self {
+        HallTable([
+            None,
+            Some(CommutationState::AB),
+            Some(CommutationState::BC),
+            Some(CommutationState::AC),
+            Some(CommutationState::CA),
+            Some(CommutationState::CB),
+            Some(CommutationState::BA),
+            None,
+        ])
+    }
+}
+
+/// Every commutation state, in the order of their 3-bit encoding in `HallTable::to_word`
+const STATES: [CommutationState; 6] = [
+    CommutationState::AB,
+    CommutationState::AC,
+    CommutationState::BC,
+    CommutationState::BA,
+    CommutationState::CA,
+    CommutationState::CB,
+];
+
+impl HallTable {
+    /// Pack the table into a word for storage, 3 bits per code with 0 for `None`
+    pub fn to_word(&self) -> u32 {
+        self.0.iter().enumerate().fold(0, |word, (code, state)| {
+            let bits = match state {
+                Some(state) => STATES.iter().position(|s| s == state).unwrap() as u32 + 1,
+                None => 0,
+            };
+            word | bits << (3 * code)
+        })
+    }
+
+    /// Unpack a word from `to_word`, returning `None` if it does not hold a table
+    pub fn from_word(word: u32) -> Option<Self> {
+        if word >> 24 != 0 {
+            return None;
+        }
+
+        let mut table = HallTable([None; 8]);
+        for (code, entry) in table.0.iter_mut().enumerate() {
+            *entry = match (word >> (3 * code) & 0b111) as usize {
+                0 => None,
+                bits @ 1..=6 => Some(STATES[bits - 1]),
+                _ => return None,
+            };
+        }
+        Some(table)
+    }
+}
+
+pub struct HallSensor<H1: InputPin, H2: InputPin, H3: InputPin> {
+    h1: H1,
+    h2: H2,
+    h3: H3,
+    pub table: HallTable,
+}
+
+impl<H1: InputPin, H2: InputPin, H3: InputPin> HallSensor<H1, H2, H3> {
+    pub fn new(h1: H1, h2: H2, h3: H3, table: HallTable) -> Self {
+        Self { h1, h2, h3, table }
+    }
+
+    /// Raw sensor state, with H1 as the least significant bit
+    pub fn code(&self) -> u8 {
+        (self.h1.is_high() as u8) | (self.h2.is_high() as u8) << 1 | (self.h3.is_high() as u8) << 2
+    }
+
+    /// Commutation state the rotor is currently aligned with
+    pub fn position(&self) -> Option<CommutationState> {
+        self.table.0[usize::from(self.code())]
+    }
+
+    /// Commutation state to drive for the current rotor position
+    ///
+    /// Leads the rotor by two steps in the direction of travel, so the field is 120° electrical
+    /// ahead at the centre of each hall sector. `direction` has the same meaning as in
+    /// `MotorDriver::step`.
+    pub fn commutation(&self, direction: bool) -> Option<CommutationState> {
+        self.position()
+            .map(|position| position.advance(direction).advance(direction))
+    }
+}
@@ -0,0 +1,444 @@
+//! HTTP/1.x request parsing, routing and response heads, without allocation
+//!
+//! `parse` is given everything received on a connection so far and can be called again whenever
+//! more arrives, so a request may be split across any number of TCP segments. Malformed input is
+//! reported as an `Error`, never a panic.
+
+use core::{
+    fmt::{self, Write},
+    str,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// Malformed, or framed in a way that is not supported
+    BadRequest,
+    /// The body cannot fit in the receive buffer
+    PayloadTooLarge,
+    /// The request line cannot fit in the receive buffer
+    UriTooLong,
+    /// The headers cannot fit in the receive buffer, or more of them are wanted than there are
+    /// slots to store them
+    HeadersTooLarge,
+}
+
+impl Error {
+    /// Status code of the response to send
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::BadRequest => 400,
+            Error::PayloadTooLarge => 413,
+            Error::UriTooLong => 414,
+            Error::HeadersTooLarge => 431,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header<'b> {
+    pub name: &'b str,
+    /// Without surrounding whitespace
+    pub value: &'b str,
+}
+
+impl<'b> Header<'b> {
+    /// Placeholder to fill header storage with
+    pub const EMPTY: Self = Header {
+        name: "",
+        value: "",
+    };
+}
+
+#[derive(Debug)]
+pub struct Request<'b, 'h> {
+    pub method: &'b str,
+    /// Path and query
+    pub target: &'b str,
+    pub version: Version,
+    pub headers: &'h [Header<'b>],
+    pub body: &'b [u8],
+}
+
+impl<'b, 'h> Request<'b, 'h> {
+    /// Value of the first header called `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&'b str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value)
+    }
+
+    /// Whether the client wants the connection kept open for another request, which HTTP/1.1
+    /// assumes unless told otherwise and HTTP/1.0 only when asked
+    pub fn keep_alive(&self) -> bool {
+        let connection = |option: &str| {
+            self.headers
+                .iter()
+                .filter(|header| header.name.eq_ignore_ascii_case("connection"))
+                .flat_map(|header| header.value.split(','))
+                .any(|value| value.trim().eq_ignore_ascii_case(option))
+        };
+
+        match self.version {
+            Version::Http11 => !connection("close"),
+            Version::Http10 => connection("keep-alive"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Status<'b, 'h> {
+    /// The request is not complete yet
+    Partial,
+    /// A whole request, and how many bytes of the input it took up
+    Complete(Request<'b, 'h>, usize),
+}
+
+/// Parse the request at the start of `input`
+///
+/// `capacity` is the most the caller can buffer, so that a request which could never complete is
+/// reported instead of waited on. Only headers called one of `wanted`, ignoring case, are stored in
+/// `headers`, whose length limits how many of those there may be. The rest are checked and then
+/// skipped, so a client may send as many as fit in `capacity`. Bodies are only framed by
+/// `Content-Length`.
+pub fn parse<'b, 'h>(
+    input: &'b [u8],
+    capacity: usize,
+    wanted: &[&str],
+    headers: &'h mut [Header<'b>],
+) -> Result<Status<'b, 'h>, Error> {
+    // The input ends mid-line, which is `error` if the line can never be completed
+    let incomplete = |error| {
+        if input.len() >= capacity {
+            Err(error)
+        } else {
+            Ok(Status::Partial)
+        }
+    };
+    let mut lines = Lines { input, position: 0 };
+
+    // Empty lines before the request line are ignored, as RFC 7230 section 3.5 suggests
+    let (method, target, version) = loop {
+        match lines.line() {
+            Some([]) => (),
+            Some(line) => break request_line(line)?,
+            None => return incomplete(Error::UriTooLong),
+        }
+    };
+
+    let mut count = 0;
+    let mut length = None;
+    loop {
+        let line = match lines.line() {
+            Some([]) => break,
+            Some(line) => line,
+            None => return incomplete(Error::HeadersTooLarge),
+        };
+        let header = header(line)?;
+
+        if header.name.eq_ignore_ascii_case("content-length") {
+            let value = content_length(header.value)?;
+            match length {
+                Some(length) if length != value => return Err(Error::BadRequest),
+                _ => length = Some(value),
+            }
+        } else if header.name.eq_ignore_ascii_case("transfer-encoding") {
+            return Err(Error::BadRequest);
+        }
+
+        if wanted
+            .iter()
+            .any(|name| header.name.eq_ignore_ascii_case(name))
+        {
+            *headers.get_mut(count).ok_or(Error::HeadersTooLarge)? = header;
+            count += 1;
+        }
+    }
+    let headers: &'h [Header<'b>] = headers;
+
+    let end = lines
+        .position
+        .checked_add(length.unwrap_or(0))
+        .filter(|&end| end <= capacity)
+        .ok_or(Error::PayloadTooLarge)?;
+    let body = match input.get(lines.position..end) {
+        Some(body) => body,
+        None => return Ok(Status::Partial),
+    };
+
+    Ok(Status::Complete(
+        Request {
+            method,
+            target,
+            version,
+            headers: &headers[..count],
+            body,
+        },
+        end,
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+impl Method {
+    const ALL: [Method; 2] = [Method::Get, Method::Post];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+        }
+    }
+}
+
+/// One entry of a routing table
+pub struct Route<T> {
+    pub method: Method,
+    /// Without the query
+    pub path: &'static str,
+    pub handler: T,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Routed<T> {
+    Found(T),
+    NotFound,
+    /// The path exists, but not for this method
+    MethodNotAllowed(Allow),
+}
+
+/// Methods a path can be requested with, written as the value of an `Allow` header
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Allow {
+    get: bool,
+    post: bool,
+}
+
+impl Allow {
+    pub fn contains(&self, method: Method) -> bool {
+        match method {
+            Method::Get => self.get,
+            Method::Post => self.post,
+        }
+    }
+}
+
+impl fmt::Display for Allow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut separator = "";
+        for &method in Method::ALL.iter().filter(|&&method| self.contains(method)) {
+            f.write_str(separator)?;
+            f.write_str(method.as_str())?;
+            if method == Method::Get {
+                f.write_str(", HEAD")?;
+            }
+            separator = ", ";
+        }
+        Ok(())
+    }
+}
+
+/// Find the handler for `method` and `path`
+///
+/// `HEAD` is answered by the `GET` route, the caller leaving out the body.
+pub fn route<T: Copy>(routes: &[Route<T>], method: &str, path: &str) -> Routed<T> {
+    let mut allow = Allow {
+        get: false,
+        post: false,
+    };
+    let method = match method {
+        "HEAD" => "GET",
+        method => method,
+    };
+
+    for route in routes.iter().filter(|route| route.path == path) {
+        if route.method.as_str() == method {
+            return Routed::Found(route.handler);
+        }
+        match route.method {
+            Method::Get => allow.get = true,
+            Method::Post => allow.post = true,
+        }
+    }
+
+    if allow.get || allow.post {
+        Routed::MethodNotAllowed(allow)
+    } else {
+        Routed::NotFound
+    }
+}
+
+/// Status line and headers of a response, written out by `Display`
+pub struct Response<'a> {
+    pub status: u16,
+    /// Left out when there is no body
+    pub content_type: Option<&'a str>,
+    pub content_length: usize,
+    /// Whether the connection stays open for another request
+    pub keep_alive: bool,
+    pub allow: Option<Allow>,
+    /// Any other headers
+    pub headers: &'a [Header<'a>],
+}
+
+impl<'a> Response<'a> {
+    /// Response without a body
+    pub fn empty(status: u16) -> Self {
+        Self {
+            status,
+            content_type: None,
+            content_length: 0,
+            keep_alive: false,
+            allow: None,
+            headers: &[],
+        }
+    }
+}
+
+impl<'a> fmt::Display for Response<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "HTTP/1.1 {} {}\r\n", self.status, reason(self.status))?;
+        if let Some(content_type) = self.content_type {
+            write!(f, "Content-Type: {}\r\n", content_type)?;
+        }
+        write!(f, "Content-Length: {}\r\n", self.content_length)?;
+        match self.keep_alive {
+            true => f.write_str("Connection: keep-alive\r\n")?,
+            false => f.write_str("Connection: close\r\n")?,
+        }
+        if let Some(allow) = self.allow {
+            write!(f, "Allow: {}\r\n", allow)?;
+        }
+        for header in self.headers {
+            write!(f, "{}: {}\r\n", header.name, header.value)?;
+        }
+        f.write_str("\r\n")
+    }
+}
+
+/// Reason phrase for the status codes this server sends
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
+
+/// Length of `body` once written out, so that its `Content-Length` can be sent ahead of it
+/// without buffering it
+pub fn length(body: &impl fmt::Display) -> usize {
+    struct Counter(usize);
+
+    impl Write for Counter {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    write!(counter, "{}", body).ok();
+    counter.0
+}
+
+/// Splits complete lines off the input, each ending in LF with an optional CR before it
+struct Lines<'b> {
+    input: &'b [u8],
+    position: usize,
+}
+
+impl<'b> Lines<'b> {
+    fn line(&mut self) -> Option<&'b [u8]> {
+        let rest = self.input.get(self.position..)?;
+        let end = rest.iter().position(|&b| b == b'\n')?;
+        self.position += end + 1;
+
+        let line = &rest[..end];
+        Some(match line.split_last() {
+            Some((b'\r', line)) => line,
+            _ => line,
+        })
+    }
+}
+
+fn request_line(line: &[u8]) -> Result<(&str, &str, Version), Error> {
+    let mut parts = line.split(|&b| b == b' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Err(Error::BadRequest),
+    };
+
+    if !is_token(method) || target.is_empty() || !target.iter().all(|&b| b > b' ' && b < 0x7f) {
+        return Err(Error::BadRequest);
+    }
+    let version = match version {
+        b"HTTP/1.1" => Version::Http11,
+        b"HTTP/1.0" => Version::Http10,
+        _ => return Err(Error::BadRequest),
+    };
+
+    Ok((utf8(method)?, utf8(target)?, version))
+}
+
+fn header(line: &[u8]) -> Result<Header<'_>, Error> {
+    let colon = line
+        .iter()
+        .position(|&b| b == b':')
+        .ok_or(Error::BadRequest)?;
+    let (name, value) = line.split_at(colon);
+    let value = &value[1..];
+
+    // Rules out whitespace before the colon, and obsolete line folding
+    if !is_token(name) {
+        return Err(Error::BadRequest);
+    }
+    if !value
+        .iter()
+        .all(|&b| b == b'\t' || (b >= b' ' && b != 0x7f))
+    {
+        return Err(Error::BadRequest);
+    }
+
+    Ok(Header {
+        name: utf8(name)?,
+        value: utf8(value)?.trim_matches(|c| c == ' ' || c == '\t'),
+    })
+}
+
+fn content_length(value: &str) -> Result<usize, Error> {
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(Error::BadRequest);
+    }
+
+    // Only digits are left, so this can only fail by overflowing
+    value.parse().map_err(|_| Error::PayloadTooLarge)
+}
+
+fn is_token(bytes: &[u8]) -> bool {
+    !bytes.is_empty()
+        && bytes
+            .iter()
+            .all(|&b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn utf8(bytes: &[u8]) -> Result<&str, Error> {
+    str::from_utf8(bytes).map_err(|_| Error::BadRequest)
+}
@@ -0,0 +1,329 @@
+//! Motor parameter identification
+//!
+//! Runs once per PWM period with the latest current samples. Phase resistance is measured by
+//! regulating a DC current through phases A and B, inductance from the current ripple of a
+//! square wave across the same pair, and flux linkage from the back-EMF after spinning the rotor
+//! up open loop and letting it coast.
+
+use {
+    crate::{
+        motor::{CommutationState, MotorDriver, PhaseDriver},
+        pid::clamp,
+        Samples,
+    },
+    core::f32::consts::PI,
+};
+
+/// Current regulated through phases A and B while measuring resistance, in amps
+const RESISTANCE_CURRENT: f32 = 5.0;
+/// Duty cycle change per PWM period per amp of error while regulating that current
+const RESISTANCE_GAIN: f32 = 0.000_02;
+const RESISTANCE_MAX_DUTY: f32 = 0.3;
+/// Duty cycle of the inductance square wave
+const INDUCTANCE_DUTY: f32 = 0.2;
+/// PWM periods spent on each half of the square wave
+const INDUCTANCE_HALF_PERIODS: u32 = 2;
+/// Duty cycle while spinning up for the flux linkage measurement
+const SPIN_DUTY: f32 = 0.15;
+/// Open-loop step length at the start and end of the spin up, in PWM periods
+const SPIN_START_PERIOD: u32 = 1_000;
+const SPIN_END_PERIOD: u32 = 50;
+/// Steps taken at the final speed before coasting
+const SPIN_HOLD_STEPS: u32 = 60;
+
+/// PWM periods allowed for the current to settle before each measurement
+const SETTLE_PERIODS: u32 = 4_000;
+/// PWM periods averaged by each measurement
+const MEASURE_PERIODS: u32 = 4_000;
+/// PWM periods the back-EMF is watched for after releasing the rotor
+const COAST_PERIODS: u32 = 200;
+/// Phase current below which the body diodes have stopped conducting after the release, in amps
+const COAST_CURRENT: f32 = 0.05;
+
+/// Smallest peak to peak ripple and line-to-line back-EMF accepted as real, in amps and volts
+const MIN_RIPPLE: f32 = 0.1;
+const MIN_BACK_EMF: f32 = 0.5;
+
+const SQRT_3: f32 = 1.732_050_8;
+
+/// Identified per-phase parameters
+#[derive(Debug, Clone, Copy)]
+pub struct MotorParameters {
+    /// Ohms
+    pub resistance: f32,
+    /// Henries
+    pub inductance: f32,
+    /// Webers, peak phase back-EMF per electrical radian per second
+    pub flux_linkage: f32,
+}
+
+impl MotorParameters {
+    /// Current loop gains in volts per amp and per amp-second, for a closed loop bandwidth in
+    /// radians per second
+    pub fn current_gains(&self, bandwidth: f32) -> (f32, f32) {
+        (self.inductance * bandwidth, self.resistance * bandwidth)
+    }
+
+    pub fn to_words(&self) -> [u32; 3] {
+        [
+            self.resistance.to_bits(),
+            self.inductance.to_bits(),
+            self.flux_linkage.to_bits(),
+        ]
+    }
+
+    pub fn from_words(words: &[u32; 3]) -> Self {
+        Self {
+            resistance: f32::from_bits(words[0]),
+            inductance: f32::from_bits(words[1]),
+            flux_linkage: f32::from_bits(words[2]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum IdentifyError {
+    /// No current flowed at the largest duty cycle tried
+    NoCurrent,
+    /// The square wave produced no measurable ripple
+    NoRipple,
+    /// The rotor produced no back-EMF after spinning up
+    NoBackEmf,
+}
+
+impl IdentifyError {
+    pub fn description(&self) -> &'static str {
+        match self {
+            IdentifyError::NoCurrent => "no current flowing",
+            IdentifyError::NoRipple => "no current ripple",
+            IdentifyError::NoBackEmf => "no back-emf, rotor did not spin",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Stopped,
+    Resistance,
+    /// Phases floating while the resistance measurement current decays
+    Pause,
+    Inductance,
+    Spin,
+    Coast,
+}
+
+pub struct Identify {
+    stage: Stage,
+    /// PWM periods since the stage began
+    ticks: u32,
+    /// Regulated duty cycle while measuring resistance
+    duty: f32,
+    /// Sums of applied voltage and measured current, or of ripple and cycles while measuring
+    /// inductance
+    voltage: f32,
+    current: f32,
+    /// Current range over the present square wave cycle
+    min: f32,
+    max: f32,
+    /// Open-loop step length and steps taken at it while spinning
+    period: u32,
+    steps: u32,
+    /// Largest line-to-line voltage seen while coasting
+    peak: f32,
+    resistance: f32,
+    inductance: f32,
+    erpm: f32,
+    result: Option<Result<MotorParameters, IdentifyError>>,
+}
+
+impl Identify {
+    pub const fn new() -> Self {
+        Self {
+            stage: Stage::Stopped,
+            ticks: 0,
+            duty: 0.0,
+            voltage: 0.0,
+            current: 0.0,
+            min: 0.0,
+            max: 0.0,
+            period: SPIN_START_PERIOD,
+            steps: 0,
+            peak: 0.0,
+            resistance: 0.0,
+            inductance: 0.0,
+            erpm: 0.0,
+            result: None,
+        }
+    }
+
+    pub fn start(&mut self) {
+        *self = Self::new();
+        self.stage = Stage::Resistance;
+    }
+
+    /// Abandon identification, leaving the phases to the caller
+    pub fn stop(&mut self) {
+        self.stage = Stage::Stopped;
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.stage != Stage::Stopped
+    }
+
+    pub fn result(&self) -> Option<Result<MotorParameters, IdentifyError>> {
+        self.result
+    }
+
+    /// Speed the flux linkage was measured at, in electrical RPM
+    pub fn erpm(&self) -> f32 {
+        self.erpm
+    }
+
+    /// Advance by one PWM period of `dt` seconds
+    ///
+    /// `line_voltage` reads the voltage from phase A to phase B, and is only called while the
+    /// rotor is coasting. Leaves the driver idle when finished.
+    pub fn update<A: PhaseDriver, B: PhaseDriver, C: PhaseDriver>(
+        &mut self,
+        driver: &mut MotorDriver<A, B, C>,
+        samples: &Samples,
+        dt: f32,
+        line_voltage: impl FnOnce() -> f32,
+    ) {
+        let current = samples.phase_current[0];
+        self.ticks += 1;
+
+        match self.stage {
+            Stage::Stopped => (),
+            Stage::Resistance => {
+                self.duty = clamp(
+                    self.duty + RESISTANCE_GAIN * (RESISTANCE_CURRENT - current),
+                    0.0,
+                    RESISTANCE_MAX_DUTY,
+                );
+                driver.hold(CommutationState::AB, self.duty);
+
+                if self.ticks > SETTLE_PERIODS {
+                    self.voltage += self.duty * samples.bus_voltage;
+                    self.current += current;
+                }
+
+                if self.ticks >= SETTLE_PERIODS + MEASURE_PERIODS {
+                    if self.current < RESISTANCE_CURRENT * MEASURE_PERIODS as f32 / 2.0 {
+                        return self.finish(driver, Err(IdentifyError::NoCurrent));
+                    }
+                    // Phases A and B are in series
+                    self.resistance = self.voltage / (2.0 * self.current);
+                    self.next(Stage::Pause);
+                    driver.set_idle();
+                }
+            }
+            Stage::Pause => {
+                if self.ticks >= SETTLE_PERIODS {
+                    self.next(Stage::Inductance);
+                }
+            }
+            Stage::Inductance => {
+                let cycle = self.ticks % (2 * INDUCTANCE_HALF_PERIODS);
+                if cycle == 0 {
+                    // The first cycles start from rest, so leave them out
+                    if self.ticks > 4 * INDUCTANCE_HALF_PERIODS {
+                        self.voltage += self.max - self.min;
+                        self.current += 1.0;
+                    }
+                    self.min = current;
+                    self.max = current;
+                } else {
+                    self.min = self.min.min(current);
+                    self.max = self.max.max(current);
+                }
+
+                let state = if cycle < INDUCTANCE_HALF_PERIODS {
+                    CommutationState::AB
+                } else {
+                    CommutationState::BA
+                };
+                driver.hold(state, INDUCTANCE_DUTY);
+
+                if self.ticks >= MEASURE_PERIODS {
+                    let ripple = self.voltage / self.current;
+                    if ripple < MIN_RIPPLE {
+                        return self.finish(driver, Err(IdentifyError::NoRipple));
+                    }
+
+                    // Half a cycle at V across two phases in series moves the current by the
+                    // peak to peak ripple
+                    let half = INDUCTANCE_HALF_PERIODS as f32 * dt;
+                    self.inductance = INDUCTANCE_DUTY * samples.bus_voltage * half / (2.0 * ripple);
+                    self.next(Stage::Spin);
+                    driver.hold(CommutationState::AB, SPIN_DUTY);
+                }
+            }
+            Stage::Spin => {
+                if self.ticks < self.period {
+                    return;
+                }
+                self.ticks = 0;
+
+                if self.period > SPIN_END_PERIOD {
+                    self.period = core::cmp::max(self.period - self.period / 16, SPIN_END_PERIOD);
+                } else {
+                    self.steps += 1;
+                }
+                driver.hold(driver.comm_state.next(), SPIN_DUTY);
+
+                if self.steps >= SPIN_HOLD_STEPS {
+                    self.erpm = 60.0 / (6.0 * self.period as f32 * dt);
+                    self.next(Stage::Coast);
+                    driver.set_idle();
+                }
+            }
+            Stage::Coast => {
+                // Until the current has decayed the diodes clamp the phases to the rails
+                let decayed = samples
+                    .phase_current
+                    .iter()
+                    .all(|&i| i < COAST_CURRENT && i > -COAST_CURRENT);
+                if decayed {
+                    let voltage = line_voltage();
+                    self.peak = self
+                        .peak
+                        .max(if voltage < 0.0 { -voltage } else { voltage });
+                }
+
+                if self.ticks >= COAST_PERIODS {
+                    // The peak line-to-line back-EMF is √3 times the peak phase back-EMF
+                    let omega = self.erpm * 2.0 * PI / 60.0;
+                    let flux_linkage = self.peak / (SQRT_3 * omega);
+                    if self.peak < MIN_BACK_EMF {
+                        return self.finish(driver, Err(IdentifyError::NoBackEmf));
+                    }
+
+                    let parameters = MotorParameters {
+                        resistance: self.resistance,
+                        inductance: self.inductance,
+                        flux_linkage,
+                    };
+                    self.finish(driver, Ok(parameters));
+                }
+            }
+        }
+    }
+
+    fn next(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.ticks = 0;
+        self.voltage = 0.0;
+        self.current = 0.0;
+    }
+
+    fn finish<A: PhaseDriver, B: PhaseDriver, C: PhaseDriver>(
+        &mut self,
+        driver: &mut MotorDriver<A, B, C>,
+        result: Result<MotorParameters, IdentifyError>,
+    ) {
+        driver.set_idle();
+        self.stage = Stage::Stopped;
+        self.result = Some(result);
+    }
+}
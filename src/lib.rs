@@ -0,0 +1,41 @@
+//! Motor control logic, kept apart from the hardware so that it also builds for the host
+//!
+//! The firmware in `main.rs` wires this to the board, the simulator in `sim/` to a model motor.
+
+#![no_std]
+
+pub mod arming;
+pub mod config;
+pub mod crash;
+pub mod current;
+pub mod deadman;
+pub mod fault;
+pub mod foc;
+pub mod gate;
+pub mod hall;
+pub mod http;
+pub mod identify;
+pub mod load;
+pub mod motor;
+pub mod ntc;
+pub mod pid;
+pub mod ramp;
+pub mod sensorless;
+pub mod speed;
+
+/// Phase currents and bus voltage from one set of injected ADC conversions, scaled
+#[derive(Debug, Clone, Copy)]
+pub struct Samples {
+    /// Amps flowing into the motor, with phase C inferred from A and B
+    pub phase_current: [f32; 3],
+    pub bus_voltage: f32,
+}
+
+impl Samples {
+    pub const fn new() -> Self {
+        Self {
+            phase_current: [0.0; 3],
+            bus_voltage: 0.0,
+        }
+    }
+}
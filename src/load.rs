@@ -0,0 +1,38 @@
+//! CPU load, from how much of the time the processor spends asleep
+
+/// Fraction of the time spent awake, averaged over windows of at least a fixed number of cycles
+pub struct Load {
+    window: u32,
+    awake: u32,
+    asleep: u32,
+    load: f32,
+}
+
+impl Load {
+    pub const fn new(window: u32) -> Self {
+        Self {
+            window,
+            awake: 0,
+            asleep: 0,
+            load: 0.0,
+        }
+    }
+
+    /// Load over the last complete window, from 0 to 1
+    pub fn load(&self) -> f32 {
+        self.load
+    }
+
+    /// Count `awake` cycles of work followed by `asleep` cycles of sleep
+    pub fn record(&mut self, awake: u32, asleep: u32) {
+        self.awake = self.awake.saturating_add(awake);
+        self.asleep = self.asleep.saturating_add(asleep);
+
+        let total = self.awake.saturating_add(self.asleep);
+        if total >= self.window {
+            self.load = self.awake as f32 / total as f32;
+            self.awake = 0;
+            self.asleep = 0;
+        }
+    }
+}
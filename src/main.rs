@@ -3,83 +3,221 @@
 
 #[macro_use]
 extern crate cortex_m;
-extern crate panic_semihosting;
 
-mod motor;
+mod adc;
+mod board;
+mod clock;
+mod flash;
+mod panic;
+mod pwm;
+mod watchdog;
 
 use {
-    crate::motor::{ControlState, MotorDriver, Phase},
-    core::fmt::Write,
+    crate::{
+        adc::Adc,
+        board::{Board, Hardware, Selected},
+        flash::Flash,
+        watchdog::{ResetCause, Watchdog},
+    },
+    core::fmt::{self, Write},
+    crankshaft::{
+        arming::{Arming, Rejection},
+        config::{self, Control},
+        current::CurrentLoop,
+        deadman::{Deadman, Source},
+        fault::{self, Fault, Faults},
+        foc::Foc,
+        gate::GateDriver,
+        hall::{HallSensor, HallTable},
+        http::{self, Header, Method, Route, Routed},
+        identify::{Identify, IdentifyError, MotorParameters},
+        load::Load,
+        motor::{BrakeMode, Commutation, ControlState, HallDetectError, HallDetector},
+        ntc::Temperatures,
+        pid::{self, Pid},
+        ramp::Ramp,
+        sensorless::{self, Action, Sensorless, Stage},
+        speed::Tachometer,
+        Samples,
+    },
     enc28j60::{smoltcp_phy::Phy, Enc28j60},
-    heapless::{consts::U16, Vec},
     rtfm::app,
     smoltcp::{
-        iface::{EthernetInterfaceBuilder, NeighborCache},
-        socket::{SocketSet, TcpSocket, TcpSocketBuffer},
+        iface::{EthernetInterface, EthernetInterfaceBuilder, Neighbor, NeighborCache},
+        socket::{SocketHandle, SocketSet, SocketSetItem, TcpSocket, TcpSocketBuffer},
+        time::{Duration, Instant},
         wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address},
     },
-    stm32f4xx_hal::{
-        gpio::{
-            gpioa::{PA3, PA4, PA5, PA6, PA7},
-            gpiod::PD14,
-            gpiod::{PD1, PD2, PD3, PD4, PD5, PD6},
-            Alternate, Output, PushPull, AF5,
-        },
-        prelude::*,
-        spi::Spi,
-        stm32::{self as device, SPI1},
-    },
+    stm32f4xx_hal::{prelude::*, stm32 as device},
 };
 
 const CPU_HZ: u32 = 50_000_000;
+const PWM_HZ: u32 = 20_000;
+const BRAKE_DUTY: f32 = 0.5;
+const MOTOR_TASK_HZ: u32 = 128;
+/// Time both idle and `motor_task` have to show they are running
+const WATCHDOG_TIMEOUT_MS: u32 = 250;
+/// Time allowed while the flash is erased, which stalls everything
+const FLASH_WRITE_TIMEOUT_MS: u32 = 4_000;
+/// d and q axis current loops, which output volts
+const CURRENT_PID: Pid = Pid::new(
+    config::MOTOR.foc_kp,
+    config::MOTOR.foc_ki,
+    0.0,
+    config::MOTOR.foc_max_voltage,
+    -config::MOTOR.foc_max_voltage,
+    config::MOTOR.foc_max_voltage,
+);
+/// Speed loop, which outputs a duty cycle or amps depending on `config::MOTOR.control`
+const SPEED_PID: Pid = Pid::new(
+    config::MOTOR.speed_kp,
+    config::MOTOR.speed_ki,
+    config::MOTOR.speed_kd,
+    config::MOTOR.speed_integral_limit,
+    config::MOTOR.speed_output_min,
+    config::MOTOR.speed_output_max,
+);
+/// Six-step current loop, which outputs a duty cycle
+const SIX_STEP_CURRENT_PID: Pid = Pid::new(
+    config::MOTOR.current_kp,
+    config::MOTOR.current_ki,
+    0.0,
+    1.0,
+    0.0,
+    1.0,
+);
 
-static INDEX_HEADER: &'static [u8] = b"HTTP/1.1 200 OK\r\nContent-Encoding: br\r\n\r\n";
 static INDEX_BODY: &'static [u8] = include_bytes!("../index.html.br");
-static STATUS_HEADER: &'static [u8] =
-    b"HTTP/1.1 200 OK\r\nAccess-Control-Allow-Origin: http://192.168.1.2\r\n\r\n";
+/// The web UI is stored compressed
+static INDEX_HEADERS: &'static [Header<'static>] = &[Header {
+    name: "Content-Encoding",
+    value: "br",
+}];
+static JSON_HEADERS: &'static [Header<'static>] = &[Header {
+    name: "Access-Control-Allow-Origin",
+    value: "http://192.168.1.2",
+}];
+static ROUTES: &'static [Route<Endpoint>] = &[
+    get("/", Endpoint::Index),
+    get("/status", Endpoint::Status),
+    get("/hall", Endpoint::Hall),
+    get("/panic", Endpoint::Panic),
+    get("/identify", Endpoint::Identify),
+    post("/identify/save", Endpoint::IdentifySave),
+    post("/hall/save", Endpoint::HallSave),
+    post("/arm", Endpoint::Control),
+    post("/disarm", Endpoint::Control),
+    post("/f", Endpoint::Control),
+    post("/r", Endpoint::Control),
+    post("/s", Endpoint::Control),
+    post("/brake/coast", Endpoint::Control),
+    post("/brake/short", Endpoint::Control),
+    post("/brake/dynamic", Endpoint::Control),
+    post("/speed", Endpoint::Control),
+    post("/current", Endpoint::Control),
+    post("/throttle", Endpoint::Control),
+    post("/speed/pid", Endpoint::Control),
+    post("/deadman", Endpoint::Control),
+    post("/hall/detect", Endpoint::Control),
+    post("/identify", Endpoint::Control),
+    post("/fault/clear", Endpoint::Control),
+];
 
 const SRC_MAC: [u8; 6] = [0x20, 0x18, 0x03, 0x01, 0x00, 0x00];
-const CHUNK_SIZE: usize = 256;
+/// Clients served at once, each with its own socket
+const CONNECTIONS: usize = 4;
+const SOCKET_BUFFER: usize = 1024;
+/// Longest request that can be received, headers and body together
+const REQUEST_BUFFER: usize = 1024;
+/// `Connection` headers kept from a request, every other header is skipped
+const REQUEST_HEADERS: usize = 4;
+/// Silence after which a connection is dropped, so idle keep-alive clients free their socket
+const CONNECTION_TIMEOUT_MS: u64 = 10_000;
+/// Longest `network` sleeps between runs of its own, so timers that come due sooner while it is
+/// serving a packet are late by no more than this
+const NETWORK_SLEEP_MS: u64 = 50;
+
+type Driver = board::Driver<Selected>;
+type Hall =
+    HallSensor<<Selected as Board>::Hall1, <Selected as Board>::Hall2, <Selected as Board>::Hall3>;
+type Gate = <Selected as Board>::Gate;
+type Led = <Selected as Board>::Led;
+type Eth = Phy<
+    'static,
+    <Selected as Board>::EthernetSpi,
+    <Selected as Board>::EthernetNcs,
+    <Selected as Board>::EthernetInt,
+    <Selected as Board>::EthernetReset,
+>;
+
+/// Why `network` ran
+#[derive(Debug, Clone, Copy)]
+enum Wake {
+    /// The ENC28J60 has received a packet
+    Packet,
+    /// smoltcp may have something due
+    Timeout,
+}
 
 #[app(device = stm32f4xx_hal::stm32)]
 const APP: () = {
-    static mut LED: PD14<Output<PushPull>> = ();
+    static mut LED: Led = ();
     static mut ITM: cortex_m::peripheral::ITM = ();
-    static mut ETH: Phy<
-        'static,
-        Spi<
-            SPI1,
-            (
-                PA5<Alternate<AF5>>,
-                PA6<Alternate<AF5>>,
-                PA7<Alternate<AF5>>,
-            ),
-        >,
-        PA4<Output<PushPull>>,
-        enc28j60::Unconnected,
-        PA3<Output<PushPull>>,
-    > = ();
-
-    static mut MOTOR_DRIVER: MotorDriver<
-        PD1<Output<PushPull>>,
-        PD2<Output<PushPull>>,
-        PD3<Output<PushPull>>,
-        PD4<Output<PushPull>>,
-        PD5<Output<PushPull>>,
-        PD6<Output<PushPull>>,
-    > = ();
+    static mut IFACE: EthernetInterface<'static, 'static, 'static, Eth> = ();
+    static mut SOCKETS: SocketSet<'static, 'static, 'static> = ();
+    static mut CLIENTS: [Connection; CONNECTIONS] = ();
+
+    static mut MOTOR_DRIVER: Driver = ();
+    static mut HALL: Hall = ();
+    static mut HALL_DETECTOR: HallDetector = HallDetector::new();
+    static mut HALL_RESULT: Option<Result<HallTable, HallDetectError>> = None;
+    static mut IDENTIFY: Identify = Identify::new();
+    static mut STORAGE: Flash = ();
+    static mut GATE: Gate = ();
+    static mut WATCHDOG: Watchdog = ();
+    static mut RESET_CAUSE: ResetCause = ();
+    static mut SENSORLESS: Sensorless = Sensorless::new();
+    static mut FOC: Foc = Foc::new(CURRENT_PID, CURRENT_PID);
+    static mut ANALOG: Adc = ();
+    static mut SAMPLES: Samples = Samples::new();
+    static mut TEMPERATURES: Temperatures = Temperatures::new();
+    static mut FAULTS: Faults = Faults::new(config::LIMITS);
+    static mut TACHOMETER: Tachometer = Tachometer::new(PWM_HZ);
+    static mut SPEED_LOOP: Pid = SPEED_PID;
+    static mut CURRENT_LOOP: CurrentLoop = CurrentLoop::new(SIX_STEP_CURRENT_PID);
     static mut MOTOR_CONTROL: ControlState = ControlState::Idle;
+    static mut ARMING: Arming = Arming::new();
+    static mut DEADMAN: Deadman = Deadman::new(config::COMMAND_TIMEOUT, config::THROTTLE_TIMEOUT);
+    static mut RAMP: Ramp = Ramp::new(
+        config::MOTOR.forward,
+        config::MOTOR.ramp,
+        config::MOTOR.reversal_brake,
+    );
+
+    static mut CPU_LOAD: Load = Load::new(CPU_HZ);
 
     static mut RX_BUF: [u8; 1024] = [0u8; 1024];
     static mut TX_BUF: [u8; 1024] = [0u8; 1024];
+    static mut NEIGHBORS: [Option<(IpAddress, Neighbor)>; 16] = [None; 16];
+    static mut SOCKET_STORAGE: [Option<SocketSetItem<'static, 'static>>; CONNECTIONS] =
+        [None, None, None, None];
+    /// Receive and transmit buffers for each socket
+    static mut SOCKET_BUFFERS: [[u8; SOCKET_BUFFER]; 2 * CONNECTIONS] =
+        [[0; SOCKET_BUFFER]; 2 * CONNECTIONS];
 
-    #[init(resources = [RX_BUF, TX_BUF], schedule = [motor_task])]
+    #[init(
+        resources = [RX_BUF, TX_BUF, NEIGHBORS, SOCKET_STORAGE, SOCKET_BUFFERS, FOC, FAULTS],
+        schedule = [motor_task, network]
+    )]
     fn init() {
         let mut core: rtfm::Peripherals = core;
         let device: device::Peripherals = device;
 
-        let gpioa = device.GPIOA.split();
-        let gpiod = device.GPIOD.split();
+        // Whatever hung may have been driving the motor, so stay idle until the fault is cleared
+        let reset_cause = watchdog::reset_cause();
+        if reset_cause == ResetCause::Watchdog {
+            resources.FAULTS.latch(Fault::Watchdog);
+        }
 
         let clocks = {
             // Power mode
@@ -99,47 +237,59 @@ const APP: () = {
                 .freeze()
         };
 
+        clock::start(device.TIM2, clocks);
         let _stim = &mut core.ITM.stim[0];
 
-        // LED
-        let mut led = gpiod.pd14.into_push_pull_output();
-        // turn the LED off during initialization
-        led.set_high();
-
-        // SPI
-        let spi = {
-            let sck = gpioa.pa5.into_alternate_af5();
-            let miso = gpioa.pa6.into_alternate_af5();
-            let mosi = gpioa.pa7.into_alternate_af5();
-
-            Spi::spi1(
-                device.SPI1,
-                (sck, miso, mosi),
-                enc28j60::MODE,
-                1_000_000.hz(),
-                clocks,
-            )
-        };
-        iprintln!(_stim, "\n\ninit: spi");
+        // Stored motor parameters and hall table
+        let storage = Flash::new(device.FLASH);
+        let settings = Settings::load(&storage);
+        if let Some(parameters) = settings.parameters {
+            tune_current_loops(resources.FOC, &parameters);
+            iprintln!(_stim, "init: {:?}", parameters);
+        }
+        if let Some(table) = settings.hall {
+            iprintln!(_stim, "init: {:?}", table);
+        }
+
+        // Board pins, with the motor and gate driver off and the LED off during initialization
+        let Hardware {
+            driver: motor_driver,
+            gate: mut gate,
+            led,
+            hall: (h1, h2, h3),
+            ethernet,
+        } = Selected::init(
+            board::Peripherals {
+                gpioa: device.GPIOA,
+                gpiob: device.GPIOB,
+                gpioc: device.GPIOC,
+                gpiod: device.GPIOD,
+                tim1: device.TIM1,
+                spi1: device.SPI1,
+                spi3: device.SPI3,
+            },
+            clocks,
+            PWM_HZ.hz(),
+        );
+        pwm::listen_update();
+        iprintln!(_stim, "\n\ninit: board");
 
         // ENC28J60
         let enc28j60 = {
-            let mut rst = gpioa.pa3.into_push_pull_output();
-            rst.set_high();
-            let mut ncs = gpioa.pa4.into_push_pull_output();
-            ncs.set_high();
             let mut delay = NopDelay {};
 
-            Enc28j60::new(
-                spi,
-                ncs,
-                enc28j60::Unconnected,
-                rst,
+            let mut enc28j60 = Enc28j60::new(
+                ethernet.spi,
+                ethernet.ncs,
+                ethernet.int,
+                ethernet.rst,
                 &mut delay,
                 7168,
                 SRC_MAC,
             )
-            .unwrap()
+            .unwrap();
+            enc28j60.listen(enc28j60::Event::Pkt).unwrap();
+            enc28j60
         };
         iprintln!(_stim, "init: enc26j60");
 
@@ -147,263 +297,1405 @@ const APP: () = {
         let eth = Phy::new(enc28j60, resources.RX_BUF, resources.TX_BUF);
         iprintln!(_stim, "init: phy");
 
-        // Motor setup
-        let motor_driver = {
-            let a = Phase::new(
-                gpiod.pd1.into_push_pull_output(),
-                gpiod.pd2.into_push_pull_output(),
-            );
-            let b = Phase::new(
-                gpiod.pd3.into_push_pull_output(),
-                gpiod.pd4.into_push_pull_output(),
-            );
-            let c = Phase::new(
-                gpiod.pd5.into_push_pull_output(),
-                gpiod.pd6.into_push_pull_output(),
+        // Ethernet interface
+        let iface = {
+            let ethernet_addr = EthernetAddress(SRC_MAC);
+            let local_addr = Ipv4Address::new(192, 168, 1, 2);
+            let ip_addr = IpCidr::new(IpAddress::from(local_addr), 24);
+            let ip_addrs = singleton!(: [IpCidr; 1] = [ip_addr]).unwrap();
+            EthernetInterfaceBuilder::new(eth)
+                .ethernet_addr(ethernet_addr)
+                .ip_addrs(&mut ip_addrs[..])
+                .neighbor_cache(NeighborCache::new(&mut resources.NEIGHBORS[..]))
+                .finalize()
+        };
+        iprintln!(_stim, "init: iface");
+
+        // Sockets, one for each client served at once
+        let mut sockets = SocketSet::new(&mut resources.SOCKET_STORAGE[..]);
+        let clients = {
+            let mut buffers = resources.SOCKET_BUFFERS.iter_mut();
+            let mut client = || {
+                let rx = buffers.next().expect("Too few socket buffers");
+                let tx = buffers.next().expect("Too few socket buffers");
+                Connection::new(sockets.add(TcpSocket::new(
+                    TcpSocketBuffer::new(&mut rx[..]),
+                    TcpSocketBuffer::new(&mut tx[..]),
+                )))
+            };
+            [client(), client(), client(), client()]
+        };
+        iprintln!(_stim, "init: sockets");
+
+        // Gate driver, which also powers the shunt amplifiers so must be on before calibrating
+        if let Err(fault) = gate.enable(&mut NopDelay) {
+            resources.FAULTS.latch(Fault::GateDriver(fault));
+        }
+        iprintln!(_stim, "init: gate driver");
+
+        // ADC
+        let adc = {
+            // The phases were left floating when the motor driver was created
+            let mut adc = Adc::adc1(
+                device.ADC1,
+                &device.ADC_COMMON,
+                Selected::SCALING,
+                Selected::CHANNELS,
             );
-            MotorDriver::new(a, b, c)
+            adc.calibrate();
+            adc.listen_injected();
+            adc
         };
+        iprintln!(_stim, "init: adc {:?}", adc.current_offset());
+
+        // EXTI lines of the hall sensors and the ENC28J60 INT, on whichever ports the board uses
+        let rcc = unsafe { &(*device::RCC::ptr()) };
+        rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+        Selected::route_exti(&device.SYSCFG);
+
+        // Hall sensors
+        let hall = {
+            // Interrupt on both edges of every sensor
+            device
+                .EXTI
+                .rtsr
+                .modify(|r, w| unsafe { w.bits(r.bits() | Selected::HALL_EXTI) });
+            device
+                .EXTI
+                .ftsr
+                .modify(|r, w| unsafe { w.bits(r.bits() | Selected::HALL_EXTI) });
+            device
+                .EXTI
+                .imr
+                .modify(|r, w| unsafe { w.bits(r.bits() | Selected::HALL_EXTI) });
+
+            HallSensor::new(h1, h2, h3, settings.hall.unwrap_or_default())
+        };
+        iprintln!(_stim, "init: hall");
+
+        // ENC28J60 INT, which falls when a packet arrives
+        device
+            .EXTI
+            .ftsr
+            .modify(|r, w| unsafe { w.bits(r.bits() | Selected::ETHERNET_EXTI) });
+        device
+            .EXTI
+            .imr
+            .modify(|r, w| unsafe { w.bits(r.bits() | Selected::ETHERNET_EXTI) });
+        iprintln!(_stim, "init: ethernet interrupt");
+
+        schedule
+            .motor_task(rtfm::Instant::now() + (CPU_HZ / MOTOR_TASK_HZ).cycles())
+            .unwrap();
         schedule
-            .motor_task(rtfm::Instant::now() + CPU_HZ.cycles())
+            .network(rtfm::Instant::now(), Wake::Timeout)
             .unwrap();
 
+        let watchdog = Watchdog::start(device.IWDG, &device.DBGMCU, WATCHDOG_TIMEOUT_MS);
+        iprintln!(_stim, "init: watchdog, {}", reset_cause.description());
+
         iprintln!(_stim, "init: complete\n");
         LED = led;
         ITM = core.ITM;
-        ETH = eth;
+        IFACE = iface;
+        SOCKETS = sockets;
+        CLIENTS = clients;
         MOTOR_DRIVER = motor_driver;
+        GATE = gate;
+        HALL = hall;
+        ANALOG = adc;
+        STORAGE = storage;
+        WATCHDOG = watchdog;
+        RESET_CAUSE = reset_cause;
     }
 
-    #[idle(resources = [LED, ITM, ETH, MOTOR_CONTROL])]
+    /// Sleeps whenever no task is ready, measuring how long for
+    #[idle(resources = [LED, WATCHDOG, CPU_LOAD])]
     fn idle() -> ! {
-        resources.ITM.lock(|itm| {
-            iprintln!(&mut itm.stim[0], "motor task");
-        });
+        let mut woke = rtfm::Instant::now();
+        loop {
+            resources.WATCHDOG.lock(|w| w.idle_alive());
 
-        // Ethernet interface
-        let ethernet_addr = EthernetAddress(SRC_MAC);
-        let local_addr = Ipv4Address::new(192, 168, 1, 2);
-        let ip_addr = IpCidr::new(IpAddress::from(local_addr), 24);
-        let mut ip_addrs = [ip_addr];
-        let mut neighbor_storage = [None; 16];
-        let neighbor_cache = NeighborCache::new(&mut neighbor_storage[..]);
-        let mut iface = EthernetInterfaceBuilder::new(resources.ETH)
-            .ethernet_addr(ethernet_addr)
-            .ip_addrs(&mut ip_addrs[..])
-            .neighbor_cache(neighbor_cache)
-            .finalize();
-        resources.ITM.lock(|itm| {
-            iprintln!(&mut itm.stim[0], "idle: iface");
-        });
+            // With interrupts masked, waking ends the sleep without running the handler, so the
+            // time it takes is counted as awake
+            let (slept, wake) = cortex_m::interrupt::free(|_| {
+                let slept = rtfm::Instant::now();
+                cortex_m::asm::wfi();
+                (slept, rtfm::Instant::now())
+            });
+            let awake = slept.duration_since(woke).as_cycles();
+            let asleep = wake.duration_since(slept).as_cycles();
+            resources.CPU_LOAD.lock(|l| l.record(awake, asleep));
+            woke = wake;
+        }
+    }
 
-        // Sockets
-        let mut server_rx_buffer = [0; 1024];
-        let mut server_tx_buffer = [0; 1024];
-        let server_socket = TcpSocket::new(
-            TcpSocketBuffer::new(&mut server_rx_buffer[..]),
-            TcpSocketBuffer::new(&mut server_tx_buffer[..]),
-        );
+    /// Serves every client, whenever a packet arrives and whenever smoltcp has something due
+    #[task(
+        priority = 1,
+        capacity = 2,
+        schedule = [network],
+        resources = [
+            ITM,
+            IFACE,
+            SOCKETS,
+            CLIENTS,
+            MOTOR_CONTROL,
+            ARMING,
+            DEADMAN,
+            HALL_DETECTOR,
+            HALL_RESULT,
+            IDENTIFY,
+            STORAGE,
+            GATE,
+            WATCHDOG,
+            RESET_CAUSE,
+            FOC,
+            SAMPLES,
+            TEMPERATURES,
+            FAULTS,
+            TACHOMETER,
+            SPEED_LOOP,
+            RAMP,
+            CPU_LOAD
+        ]
+    )]
+    fn network(wake: Wake) {
+        let iface = resources.IFACE;
+        let sockets = resources.SOCKETS;
 
-        let mut sockets_storage = [None, None];
-        let mut sockets = SocketSet::new(&mut sockets_storage[..]);
-        let server_handle = sockets.add(server_socket);
-        resources.ITM.lock(|itm| {
-            iprintln!(&mut itm.stim[0], "idle: sockets");
-        });
+        // Until sending has to wait for something
+        let delay = loop {
+            let now = clock::now();
+            let timestamp = Instant::from_millis(now as i64);
+            if let Err(e) = iface.poll(sockets, timestamp) {
+                resources.ITM.lock(|itm| {
+                    iprintln!(&mut itm.stim[0], "Error: {:?}", e);
+                });
+            }
 
-        let mut cursor: usize = 0;
-        loop {
-            match iface.poll(&mut sockets, smoltcp::time::Instant::from_millis(0)) {
-                Ok(b) => {
-                    if b {
-                        {
-                            let mut server_socket = sockets.get::<TcpSocket>(server_handle);
-                            if !server_socket.is_open() {
-                                server_socket
-                                    .listen(80)
-                                    .expect("Failed to listen on port 80");
-                                resources.ITM.lock(|itm| {
-                                    iprintln!(&mut itm.stim[0], "tcp:80 listening");
-                                });
+            for connection in resources.CLIENTS.iter_mut() {
+                let mut socket = sockets.get::<TcpSocket>(connection.handle);
+                if !socket.is_open() {
+                    socket.listen(80).expect("Failed to listen on port 80");
+                    socket.set_timeout(Some(Duration::from_millis(CONNECTION_TIMEOUT_MS)));
+                    *connection = Connection::new(connection.handle);
+                    resources.ITM.lock(|itm| {
+                        iprintln!(&mut itm.stim[0], "tcp:80 listening");
+                    });
+                }
+
+                // The rest of a response goes out before the next request is looked at
+                if connection.sending.is_some() {
+                    connection.send(&mut socket);
+                    continue;
+                }
+
+                // Closing, or not connected yet
+                if !socket.may_send() {
+                    continue;
+                }
+
+                // Keeps anything sent after the last request
+                if connection.consumed > 0 {
+                    connection
+                        .request
+                        .copy_within(connection.consumed..connection.received, 0);
+                    connection.received -= connection.consumed;
+                    connection.consumed = 0;
+                }
+                if socket.can_recv() {
+                    connection.received += socket
+                        .recv_slice(&mut connection.request[connection.received..])
+                        .unwrap_or(0);
+                }
+
+                let mut headers = [Header::EMPTY; REQUEST_HEADERS];
+                let request = match http::parse(
+                    &connection.request[..connection.received],
+                    REQUEST_BUFFER,
+                    &["Connection"],
+                    &mut headers,
+                ) {
+                    Ok(http::Status::Complete(request, length)) => {
+                        connection.consumed = length;
+                        request
+                    }
+                    Ok(http::Status::Partial) => continue,
+                    Err(error) => {
+                        connection.keep_alive = false;
+                        let head = http::Response::empty(error.status());
+                        connection.respond(&mut socket, head, Body::Empty);
+                        continue;
+                    }
+                };
+                connection.keep_alive = request.keep_alive();
+
+                resources.ITM.lock(|itm| {
+                    iprintln!(&mut itm.stim[0], "tcp:80 receiving {:?}", request);
+                });
+
+                let (path, query) = split_query(request.target);
+                let head_only = request.method == "HEAD";
+                let mut rejected = None;
+                // `None` answers with the status
+                let json = match http::route(ROUTES, request.method, path) {
+                    Routed::Found(Endpoint::Index) => {
+                        let head = http::Response {
+                            status: 200,
+                            content_type: Some("text/html"),
+                            content_length: INDEX_BODY.len(),
+                            keep_alive: connection.keep_alive,
+                            allow: None,
+                            headers: INDEX_HEADERS,
+                        };
+                        let body = match head_only {
+                            true => Body::Empty,
+                            false => Body::Bytes(INDEX_BODY),
+                        };
+                        connection.respond(&mut socket, head, body);
+                        continue;
+                    }
+                    Routed::Found(Endpoint::Status) => None,
+                    Routed::Found(Endpoint::Hall) => {
+                        Some(Json::Hall(resources.HALL_RESULT.lock(|r| *r)))
+                    }
+                    Routed::Found(Endpoint::Panic) => Some(Json::Panic(panic::report(head_only))),
+                    Routed::Found(Endpoint::Identify) => {
+                        let (result, erpm) = resources.IDENTIFY.lock(|i| (i.result(), i.erpm()));
+                        Some(Json::Identify(result, erpm))
+                    }
+                    Routed::Found(Endpoint::IdentifySave) => {
+                        let (result, erpm) = resources.IDENTIFY.lock(|i| (i.result(), i.erpm()));
+                        let control = resources.MOTOR_CONTROL.lock(|c| *c);
+
+                        Some(match (control, result) {
+                            // Erasing stalls the motor interrupts
+                            (ControlState::Idle, Some(Ok(parameters))) => {
+                                let watchdog = &mut resources.WATCHDOG;
+                                watchdog.lock(|w| w.set_timeout(FLASH_WRITE_TIMEOUT_MS));
+                                let mut settings = Settings::load(&resources.STORAGE);
+                                settings.parameters = Some(parameters);
+                                let written = resources.STORAGE.write(&settings.to_words());
+                                watchdog.lock(|w| w.set_timeout(WATCHDOG_TIMEOUT_MS));
+                                match written {
+                                    Ok(()) => {
+                                        resources
+                                            .FOC
+                                            .lock(|foc| tune_current_loops(foc, &parameters));
+                                        Json::Identify(result, erpm)
+                                    }
+                                    Err(e) => Json::Error(500, e.description()),
+                                }
                             }
-                            if cursor == 0 && server_socket.can_recv() {
-                                let mut buf = [0u8; 1024];
-                                let len = server_socket
-                                    .recv_slice(&mut buf)
-                                    .expect("Failed to receive slice");
-
-                                let request = Request::from_str(
-                                    core::str::from_utf8(&buf[..len])
-                                        .expect("Request not valid UTF8"),
-                                )
-                                .expect("Failed to parse HTTP request");
-
-                                resources.ITM.lock(|itm| {
-                                    iprintln!(&mut itm.stim[0], "tcp:80 receiving {:?}", request);
-                                });
-
-                                if request.method == "GET" && request.route == "/" {
-                                    if server_socket.can_send() {
-                                        if cursor == 0 {
-                                            resources.ITM.lock(|itm| {
-                                                iprintln!(&mut itm.stim[0], "tcp:80 sending");
-                                            });
-                                            let len =
-                                                server_socket.send_slice(INDEX_HEADER).unwrap();
-                                            resources.ITM.lock(|itm| {
-                                                iprintln!(&mut itm.stim[0], "tcp:80 sent {}", len);
-                                            });
-                                        }
+                            (ControlState::Idle, _) => Json::Error(409, "nothing identified"),
+                            _ => Json::Error(409, "motor not idle"),
+                        })
+                    }
+                    Routed::Found(Endpoint::HallSave) => {
+                        let result = resources.HALL_RESULT.lock(|r| *r);
+                        let control = resources.MOTOR_CONTROL.lock(|c| *c);
 
-                                        let len = server_socket
-                                            .send_slice(&INDEX_BODY[cursor..(cursor + CHUNK_SIZE)])
-                                            .unwrap();
-                                        resources.ITM.lock(|itm| {
-                                            iprintln!(&mut itm.stim[0], "tcp:80 sent {}", len);
-                                        });
-                                        cursor += CHUNK_SIZE;
+                        Some(match (control, result) {
+                            // Erasing stalls the motor interrupts
+                            (ControlState::Idle, Some(Ok(table))) => {
+                                let watchdog = &mut resources.WATCHDOG;
+                                watchdog.lock(|w| w.set_timeout(FLASH_WRITE_TIMEOUT_MS));
+                                let mut settings = Settings::load(&resources.STORAGE);
+                                settings.hall = Some(table);
+                                let written = resources.STORAGE.write(&settings.to_words());
+                                watchdog.lock(|w| w.set_timeout(WATCHDOG_TIMEOUT_MS));
+                                match written {
+                                    Ok(()) => Json::Hall(result),
+                                    Err(e) => Json::Error(500, e.description()),
+                                }
+                            }
+                            (ControlState::Idle, _) => Json::Error(409, "nothing detected"),
+                            _ => Json::Error(409, "motor not idle"),
+                        })
+                    }
+                    Routed::Found(Endpoint::Control) => {
+                        let detector = &mut resources.HALL_DETECTOR;
+                        let identify = &mut resources.IDENTIFY;
+                        let result = &mut resources.HALL_RESULT;
+                        let speed_loop = &mut resources.SPEED_LOOP;
+                        let faults = &mut resources.FAULTS;
+                        let deadman = &mut resources.DEADMAN;
+                        let arming = &mut resources.ARMING;
+                        if path == "/fault/clear" {
+                            resources.GATE.lock(|g| g.reset(&mut NopDelay));
+                            faults.lock(|f| f.clear());
+                        }
+                        let fault = faults.lock(|f| f.active());
+                        let bus_voltage = resources.SAMPLES.lock(|s| s.bus_voltage);
+                        let erpm = resources.TACHOMETER.lock(|t| t.erpm());
+                        // A setpoint without a usable parameter changes nothing
+                        let mut invalid = false;
+                        resources.MOTOR_CONTROL.lock(|c| {
+                            let state = match path {
+                                "/arm" => {
+                                    rejected = arming.lock(|a| a.arm(*c, fault, bus_voltage)).err();
+                                    *c
+                                }
+                                "/disarm" => {
+                                    arming.lock(|a| a.disarm());
+                                    ControlState::Idle
+                                }
+                                "/f" => ControlState::Forward,
+                                "/r" => ControlState::Reverse,
+                                "/s" => ControlState::Idle,
+                                "/brake/coast" => ControlState::Brake(BrakeMode::Coast),
+                                "/brake/short" => ControlState::Brake(BrakeMode::Short),
+                                "/brake/dynamic" => {
+                                    ControlState::Brake(BrakeMode::Dynamic(BRAKE_DUTY))
+                                }
+                                "/speed" => match param(query, "erpm") {
+                                    Some(erpm) => ControlState::Speed(erpm),
+                                    None => {
+                                        invalid = true;
+                                        return;
+                                    }
+                                },
+                                "/current" => match param(query, "amps") {
+                                    Some(amps) => ControlState::Current(amps),
+                                    None => {
+                                        invalid = true;
+                                        return;
+                                    }
+                                },
+                                "/throttle" => match param(query, "position") {
+                                    Some(position) => {
+                                        ControlState::Current(config::MOTOR.throttle(position))
+                                    }
+                                    None => {
+                                        invalid = true;
+                                        return;
                                     }
-                                } else if request.method == "POST" {
-                                    resources.MOTOR_CONTROL.lock(|c| {
-                                        *c = match request.route {
-                                            "/f" => ControlState::Forward,
-                                            "/r" => ControlState::Reverse,
-                                            "/s" => ControlState::Idle,
-                                            _ => ControlState::Idle,
+                                },
+                                // Every gain is applied, or none if any is unknown or not a
+                                // number
+                                "/speed/pid" => {
+                                    let applied = speed_loop.lock(|pid| {
+                                        let mut updated = *pid;
+                                        for (key, value) in query {
+                                            match number(value).map(|v| updated.set(key, v)) {
+                                                Some(Ok(())) => (),
+                                                _ => return false,
+                                            }
                                         }
+                                        *pid = updated;
+                                        true
                                     });
-
-                                    resources.ITM.lock(|itm| {
-                                        iprintln!(&mut itm.stim[0], "tcp:80 sending");
-                                    });
-                                    server_socket.send_slice(STATUS_HEADER).unwrap();
-                                    write!(
-                                        server_socket,
-                                        "{{\r\n\t\"state\": \"{}\",\r\n\t\"count\": {}\r\n}}\r\n",
-                                        false, false,
-                                    )
-                                    .unwrap();
-
-                                    resources.ITM.lock(|itm| {
-                                        iprintln!(&mut itm.stim[0], "tcp:80 close");
+                                    if !applied {
+                                        invalid = true;
+                                        return;
+                                    }
+                                    *c
+                                }
+                                // Timeouts in seconds, zero to never time out, all applied or
+                                // none
+                                "/deadman" => {
+                                    let mut timeouts = [None; 2];
+                                    for (key, value) in query {
+                                        let timeout = match key {
+                                            "command" => &mut timeouts[0],
+                                            "throttle" => &mut timeouts[1],
+                                            _ => {
+                                                invalid = true;
+                                                return;
+                                            }
+                                        };
+                                        match number(value) {
+                                            Some(seconds) => *timeout = Some(seconds),
+                                            None => {
+                                                invalid = true;
+                                                return;
+                                            }
+                                        }
+                                    }
+                                    deadman.lock(|d| {
+                                        let sources = [Source::Command, Source::Throttle];
+                                        for (&source, &seconds) in sources.iter().zip(&timeouts) {
+                                            if let Some(seconds) = seconds {
+                                                d.set_timeout(
+                                                    source,
+                                                    Some(seconds).filter(|&s| s > 0.0),
+                                                );
+                                            }
+                                        }
                                     });
-                                    server_socket.close();
+                                    *c
                                 }
-                            }
+                                "/hall/detect" => ControlState::DetectHall,
+                                "/identify" => ControlState::Identify,
+                                _ => ControlState::Idle,
+                            };
+                            // A rejected request leaves the setpoint as it was
+                            let state = match arming.lock(|a| a.check(*c, state, fault, erpm)) {
+                                Ok(()) => state,
+                                Err(reason) => {
+                                    rejected = rejected.or(Some(reason));
+                                    *c
+                                }
+                            };
 
-                            if server_socket.can_send() {
-                                if cursor + CHUNK_SIZE < INDEX_BODY.len() {
-                                    let len = server_socket
-                                        .send_slice(&INDEX_BODY[cursor..(cursor + CHUNK_SIZE)])
-                                        .unwrap();
-                                    resources.ITM.lock(|itm| {
-                                        iprintln!(&mut itm.stim[0], "tcp:80 sent {}", len);
-                                    });
-                                    cursor += CHUNK_SIZE;
-                                } else if cursor + CHUNK_SIZE > INDEX_BODY.len()
-                                    && cursor < INDEX_BODY.len()
-                                {
-                                    let len =
-                                        server_socket.send_slice(&INDEX_BODY[cursor..]).unwrap();
-                                    resources.ITM.lock(|itm| {
-                                        iprintln!(&mut itm.stim[0], "tcp:80 sent {}", len);
-                                    });
-                                    cursor += CHUNK_SIZE;
-                                } else {
-                                    cursor = 0;
-                                    resources.ITM.lock(|itm| {
-                                        iprintln!(&mut itm.stim[0], "tcp:80 close");
-                                    });
-                                    server_socket.close();
+                            // Restart detection from the beginning unless it is
+                            // already running
+                            match (*c, state) {
+                                (ControlState::DetectHall, ControlState::DetectHall) => (),
+                                (_, ControlState::DetectHall) => {
+                                    detector.lock(|d| *d = HallDetector::new());
+                                    result.lock(|r| *r = None);
                                 }
+                                (ControlState::Identify, ControlState::Identify) => (),
+                                (_, ControlState::Identify) => {
+                                    identify.lock(|i| *i = Identify::new())
+                                }
+                                _ => (),
+                            }
+
+                            // Anything that drives the motor has to keep being
+                            // requested
+                            match path {
+                                _ if rejected.is_some() => (),
+                                "/speed/pid" | "/deadman" | "/arm" => (),
+                                _ => deadman.lock(|d| match state.direction() {
+                                    Some(_) if path == "/throttle" => {
+                                        d.refresh(Source::Throttle, now)
+                                    }
+                                    Some(_) => d.refresh(Source::Command, now),
+                                    None => d.release(),
+                                }),
                             }
+
+                            *c = state;
+                        });
+
+                        match invalid {
+                            true => Some(Json::Error(400, "missing or invalid parameter")),
+                            false => None,
                         }
                     }
-                }
-                Err(e) => {
-                    resources.ITM.lock(|itm| {
-                        iprintln!(&mut itm.stim[0], "Error: {:?}", e);
-                    });
-                }
+                    Routed::NotFound => {
+                        let head = http::Response {
+                            keep_alive: connection.keep_alive,
+                            ..http::Response::empty(404)
+                        };
+                        connection.respond(&mut socket, head, Body::Empty);
+                        continue;
+                    }
+                    Routed::MethodNotAllowed(allow) => {
+                        let head = http::Response {
+                            keep_alive: connection.keep_alive,
+                            allow: Some(allow),
+                            ..http::Response::empty(405)
+                        };
+                        connection.respond(&mut socket, head, Body::Empty);
+                        continue;
+                    }
+                };
+
+                let json = match json {
+                    Some(json) => json,
+                    None => Json::Status(Status {
+                        control: resources.MOTOR_CONTROL.lock(|c| *c),
+                        applied: resources.RAMP.lock(|r| r.applied()),
+                        samples: resources.SAMPLES.lock(|s| *s),
+                        erpm: resources.TACHOMETER.lock(|t| t.erpm()),
+                        temperatures: resources.TEMPERATURES.lock(|t| *t),
+                        fault: resources.FAULTS.lock(|f| f.active()),
+                        fault_history: resources.FAULTS.lock(|f| f.history()),
+                        reset: *resources.RESET_CAUSE,
+                        uptime: now,
+                        cpu_load: resources.CPU_LOAD.load(),
+                        command_timeout: resources
+                            .DEADMAN
+                            .lock(|d| d.timeout(Source::Command).seconds),
+                        throttle_timeout: resources
+                            .DEADMAN
+                            .lock(|d| d.timeout(Source::Throttle).seconds),
+                        remaining: resources.DEADMAN.lock(|d| d.remaining(now)),
+                        armed: resources.ARMING.lock(|a| a.is_armed()),
+                        gate_warning: resources.GATE.lock(|g| g.warning()),
+                        rejected,
+                    }),
+                };
+                let head = http::Response {
+                    status: json.status(),
+                    content_type: Some("application/json"),
+                    content_length: http::length(&json),
+                    keep_alive: connection.keep_alive,
+                    allow: None,
+                    headers: JSON_HEADERS,
+                };
+
+                resources.ITM.lock(|itm| {
+                    iprintln!(&mut itm.stim[0], "tcp:80 sending");
+                });
+                let body = match head_only {
+                    true => Body::Empty,
+                    false => Body::Json(json),
+                };
+                connection.respond(&mut socket, head, body);
             }
+
+            let timestamp = Instant::from_millis(clock::now() as i64);
+            match iface.poll_delay(sockets, timestamp) {
+                Some(delay) if delay == Duration::from_millis(0) => (),
+                delay => break delay,
+            }
+        };
+
+        // Only the run woken by the timeout schedules the next, so there is only ever one pending
+        if let Wake::Timeout = wake {
+            let ms = match delay {
+                Some(delay) => delay.total_millis().min(NETWORK_SLEEP_MS),
+                None => NETWORK_SLEEP_MS,
+            };
+            schedule
+                .network(
+                    rtfm::Instant::now() + (ms as u32 * (CPU_HZ / 1_000)).cycles(),
+                    Wake::Timeout,
+                )
+                .unwrap();
         }
     }
 
-    #[task(priority = 2, schedule = [motor_task], resources = [ITM, MOTOR_DRIVER, MOTOR_CONTROL])]
+    /// ENC28J60 INT
+    #[interrupt(priority = 1, spawn = [network])]
+    fn EXTI15_10() {
+        let exti = unsafe { &(*device::EXTI::ptr()) };
+        exti.pr
+            .write(|w| unsafe { w.bits(Selected::ETHERNET_EXTI) });
+
+        // Failing means a run is already waiting, which will find this packet too
+        spawn.network(Wake::Packet).ok();
+    }
+
+    #[task(
+        priority = 2,
+        schedule = [motor_task],
+        resources = [
+            ITM,
+            MOTOR_DRIVER,
+            MOTOR_CONTROL,
+            ARMING,
+            DEADMAN,
+            RAMP,
+            HALL,
+            HALL_DETECTOR,
+            HALL_RESULT,
+            IDENTIFY,
+            SENSORLESS,
+            FOC,
+            TACHOMETER,
+            SPEED_LOOP,
+            CURRENT_LOOP,
+            ANALOG,
+            TEMPERATURES,
+            FAULTS,
+            GATE,
+            WATCHDOG
+        ]
+    )]
     fn motor_task() {
+        resources.WATCHDOG.motor_alive();
+
         let _stim = &mut resources.ITM.stim[0];
+        let control = resources.MOTOR_CONTROL;
+        let hall = resources.HALL;
+        let hall_detector = resources.HALL_DETECTOR;
+        let hall_result = resources.HALL_RESULT;
+        let mut identify = resources.IDENTIFY;
+        let mut sensorless = resources.SENSORLESS;
+        let mut foc = resources.FOC;
+        let mut current_loop = resources.CURRENT_LOOP;
+        let speed_loop = resources.SPEED_LOOP;
 
-        match *resources.MOTOR_CONTROL {
-            ControlState::Idle => {
-                resources.MOTOR_DRIVER.set_idle();
-            }
-            ControlState::Forward => {
-                resources.MOTOR_DRIVER.step(false);
+        let temperatures = resources.ANALOG.lock(|analog| Temperatures {
+            fet: Selected::FET_NTC
+                .temperature(analog.ratio(analog.read(Selected::CHANNELS.fet_temperature))),
+            // Whatever the input reads without a thermistor fitted is meaningless
+            motor: config::LIMITS.max_motor_temperature.and_then(|_| {
+                Selected::MOTOR_NTC
+                    .temperature(analog.ratio(analog.read(Selected::CHANNELS.motor_temperature)))
+            }),
+        });
+        *resources.TEMPERATURES = temperatures;
+
+        // The gate driver has already shut the gates off by itself
+        if let Some(fault) = resources.GATE.fault() {
+            resources.FAULTS.lock(|f| f.latch(Fault::GateDriver(fault)));
+        }
+
+        // Current and voltage faults already floated the phases at the PWM rate, this keeps them
+        // that way
+        let fault = resources
+            .FAULTS
+            .lock(|f| f.check_temperatures(&temperatures));
+        resources.ARMING.update(fault);
+        if fault.is_some() {
+            *control = ControlState::Idle;
+            resources.DEADMAN.release();
+        }
+
+        if let Some(fallback) = resources.DEADMAN.update(clock::now()) {
+            *control = fallback;
+        }
+
+        let dt = 1.0 / MOTOR_TASK_HZ as f32;
+        let erpm = resources.TACHOMETER.lock(|t| t.erpm());
+        let applied = resources.RAMP.update(*control, erpm, dt);
+
+        let derate = derate(&temperatures);
+
+        // In the direction of the setpoint, a duty cycle under six-step or amps under FOC
+        let output = match applied {
+            ControlState::Duty(duty) => Some(if duty < 0.0 { -duty } else { duty }),
+            ControlState::Speed(setpoint) => {
+                let sign = if setpoint < 0.0 { -1.0 } else { 1.0 };
+                Some(speed_loop.update(setpoint * sign, erpm * sign, dt))
             }
-            ControlState::Reverse => {
-                resources.MOTOR_DRIVER.step(true);
+            _ => {
+                speed_loop.reset();
+                None
             }
-            _ => unimplemented!(),
         }
+        .map(|output| output * derate);
+
+        match applied {
+            ControlState::Identify => (),
+            _ => identify.lock(|i| i.stop()),
+        }
+
+        let comm_state = resources.MOTOR_DRIVER.lock(|driver| {
+            sensorless.lock(|sensorless| {
+                foc.lock(|foc| {
+                    current_loop.lock(|current_loop| {
+                        if applied.direction().is_none() {
+                            sensorless.stop();
+                            foc.stop();
+                            current_loop.stop();
+                        }
+
+                        match applied {
+                            ControlState::Idle => {
+                                driver.set_idle();
+                            }
+                            ControlState::Forward
+                            | ControlState::Reverse
+                            | ControlState::Duty(_)
+                            | ControlState::Speed(_)
+                            | ControlState::Current(_) => {
+                                let direction = match applied {
+                                    // Keep going the way the rotor turns, so that reverse torque
+                                    // regenerates rather than reversing
+                                    ControlState::Current(_) if erpm != 0.0 => erpm < 0.0,
+                                    _ => applied.direction().unwrap_or(false),
+                                };
+                                // Amps to drive with in `direction`, negative to regenerate
+                                let current = match applied {
+                                    ControlState::Current(amps) => Some(
+                                        pid::clamp(
+                                            if direction { -amps } else { amps },
+                                            -config::MOTOR.max_regen_current,
+                                            config::MOTOR.max_current,
+                                        ) * derate,
+                                    ),
+                                    _ => None,
+                                };
+
+                                // Under six-step either the current loop owns the duty cycle at
+                                // the PWM rate, or it is set here. Sensorless startup holds its
+                                // own duty cycle.
+                                let six_step = match config::MOTOR.control {
+                                    Control::SixStep(Commutation::Sensorless) => {
+                                        sensorless.stage() == Stage::Running
+                                    }
+                                    Control::SixStep(_) => true,
+                                    Control::Foc => false,
+                                };
+                                match current {
+                                    Some(amps) if six_step => current_loop.set(direction, amps),
+                                    _ => {
+                                        current_loop.stop();
+                                        if six_step {
+                                            driver.set_duty(output.unwrap_or(0.0));
+                                        }
+                                    }
+                                }
+
+                                match config::MOTOR.control {
+                                    // There is no speed feedback, so `Speed` only sets the
+                                    // direction
+                                    Control::SixStep(Commutation::OpenLoop) => {
+                                        driver.step(direction)
+                                    }
+                                    // Only matters at standstill, when there are no edges
+                                    Control::SixStep(Commutation::Hall) => {
+                                        commutate_hall(driver, hall, direction)
+                                    }
+                                    // Commutation happens at the PWM rate, this only starts it
+                                    Control::SixStep(Commutation::Sensorless) => {
+                                        match sensorless.stage() {
+                                            Stage::Stopped => {
+                                                // Running carries on at this duty cycle until
+                                                // the next run of this task
+                                                driver.set_duty(sensorless::STARTUP_DUTY);
+                                                let state = sensorless.start(direction);
+                                                driver.hold(state, sensorless::STARTUP_DUTY);
+                                            }
+                                            Stage::Lost => {
+                                                sensorless.stop();
+                                                *control = ControlState::Brake(BrakeMode::Coast);
+                                            }
+                                            _ if sensorless.direction() != direction => {
+                                                sensorless.stop();
+                                                driver.set_idle();
+                                            }
+                                            _ => (),
+                                        }
+                                    }
+                                    // The current loop runs at the PWM rate, this only starts it
+                                    Control::Foc => {
+                                        // Forward runs in `previous` order, against positive q
+                                        let iq = current.or(output).unwrap_or(0.0);
+                                        let iq = match direction {
+                                            true => iq,
+                                            false => -iq,
+                                        };
+
+                                        if foc.is_running() {
+                                            foc.set_current(iq);
+                                        } else {
+                                            match hall.position() {
+                                                Some(position) => foc.start(position, iq),
+                                                None => driver.set_idle(),
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                            ControlState::Brake(mode) => {
+                                driver.brake(mode);
+                            }
+                            ControlState::DetectHall => {
+                                if let Some(result) = hall_detector.update(driver, hall.code()) {
+                                    if let Ok(table) = result {
+                                        hall.table = table;
+                                    }
+                                    *hall_result = Some(result);
+                                    *control = ControlState::Idle;
+                                }
+                            }
+                            // Measurement happens at the PWM rate, this only starts it
+                            ControlState::Identify => identify.lock(|identify| {
+                                if identify.result().is_some() {
+                                    *control = ControlState::Idle;
+                                } else if !identify.is_running() {
+                                    identify.start();
+                                }
+                            }),
+                        }
+                    })
+                })
+            });
+
+            driver.comm_state
+        });
 
         iprintln!(
             _stim,
-            "motor task: {:?}, {:?}",
-            resources.MOTOR_CONTROL,
-            resources.MOTOR_DRIVER.comm_state
+            "motor task: {:?}, {:?}, {:?}, {} erpm, {:?}, derate {}",
+            control,
+            applied,
+            comm_state,
+            erpm,
+            fault,
+            derate
         );
 
         schedule
-            .motor_task(scheduled + (CPU_HZ / 128).cycles())
+            .motor_task(scheduled + (CPU_HZ / MOTOR_TASK_HZ).cycles())
             .unwrap();
     }
 
+    #[interrupt(
+        priority = 2,
+        resources = [MOTOR_DRIVER, RAMP, HALL, FOC, TACHOMETER, CURRENT_LOOP, FAULTS]
+    )]
+    fn EXTI9_5() {
+        let exti = unsafe { &(*device::EXTI::ptr()) };
+        exti.pr.write(|w| unsafe { w.bits(Selected::HALL_EXTI) });
+
+        let hall = resources.HALL;
+        match (config::MOTOR.control, hall.position()) {
+            // Sensorless commutation feeds the tachometer itself
+            (Control::SixStep(Commutation::Sensorless), _) | (_, None) => (),
+            (_, Some(position)) => resources.TACHOMETER.lock(|t| t.edge(position)),
+        }
+
+        if resources.FAULTS.lock(|f| f.active()).is_some() {
+            return;
+        }
+
+        match config::MOTOR.control {
+            Control::SixStep(Commutation::Hall) => {
+                let direction = match resources.RAMP.applied() {
+                    // Follows the rotor rather than the setpoint, see `motor_task`
+                    ControlState::Current(_) => resources.CURRENT_LOOP.lock(|l| l.direction()),
+                    control => control.direction(),
+                };
+                let direction = match direction {
+                    Some(direction) => direction,
+                    None => return,
+                };
+
+                resources
+                    .MOTOR_DRIVER
+                    .lock(|driver| commutate_hall(driver, hall, direction));
+            }
+            Control::Foc => {
+                if let Some(position) = hall.position() {
+                    resources.FOC.lock(|foc| foc.angle.edge(position));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Speed measurement and sensorless commutation, once per PWM period
+    #[interrupt(priority = 3, resources = [MOTOR_DRIVER, SENSORLESS, ANALOG, TACHOMETER])]
+    fn TIM1_UP_TIM10() {
+        pwm::clear_update();
+        resources.TACHOMETER.tick();
+
+        let sensorless = resources.SENSORLESS;
+        match sensorless.stage() {
+            Stage::Stopped | Stage::Lost => return,
+            _ => (),
+        }
+
+        let floating = resources
+            .ANALOG
+            .read(Selected::CHANNELS.phase_voltage[sensorless.floating()]);
+        let bus = resources.ANALOG.read(Selected::CHANNELS.bus_voltage);
+
+        match sensorless.update(floating, bus) {
+            Action::None => (),
+            Action::Commutate(state) => {
+                resources.TACHOMETER.edge(state);
+                match sensorless.stage() {
+                    Stage::Running => resources.MOTOR_DRIVER.commutate(state),
+                    _ => resources.MOTOR_DRIVER.hold(state, sensorless::STARTUP_DUTY),
+                }
+            }
+            Action::Coast => resources.MOTOR_DRIVER.set_idle(),
+        }
+    }
+
+    /// Current loops, once per PWM period in the middle of the low side on-time
+    #[interrupt(
+        priority = 3,
+        resources = [
+            MOTOR_DRIVER,
+            FOC,
+            CURRENT_LOOP,
+            IDENTIFY,
+            SENSORLESS,
+            ANALOG,
+            SAMPLES,
+            FAULTS
+        ]
+    )]
+    fn ADC() {
+        let samples = resources.ANALOG.injected();
+        *resources.SAMPLES = samples;
+
+        // Stop everything that could switch the phases back on before `motor_task` notices
+        if resources.FAULTS.check(&samples).is_some() {
+            resources.FOC.stop();
+            resources.CURRENT_LOOP.stop();
+            resources.IDENTIFY.stop();
+            resources.SENSORLESS.stop();
+            resources.MOTOR_DRIVER.set_idle();
+            return;
+        }
+
+        let dt = 1.0 / PWM_HZ as f32;
+        let [ia, ib, _] = samples.phase_current;
+        if let Some(duties) = resources.FOC.update(ia, ib, samples.bus_voltage, dt) {
+            resources.MOTOR_DRIVER.set_duties(duties);
+        }
+
+        let comm_state = resources.MOTOR_DRIVER.comm_state;
+        if let Some(duty) = resources.CURRENT_LOOP.update(&samples, comm_state, dt) {
+            resources.MOTOR_DRIVER.set_duty(duty);
+        }
+
+        if resources.IDENTIFY.is_running() {
+            let analog = resources.ANALOG;
+            resources
+                .IDENTIFY
+                .update(resources.MOTOR_DRIVER, &samples, dt, || {
+                    let a = analog.read(Selected::CHANNELS.phase_voltage[0]);
+                    let b = analog.read(Selected::CHANNELS.phase_voltage[1]);
+                    analog.voltage(a) - analog.voltage(b)
+                });
+        }
+    }
+
+    /// Extends the clock, above everything that reads it
+    #[interrupt(priority = 4)]
+    fn TIM2() {
+        clock::overflow();
+    }
+
     extern "C" {
         fn FLASH();
+        fn USART6();
     }
 };
 
-struct NopDelay;
+/// A client of the web server, and where it is up to
+struct Connection {
+    handle: SocketHandle,
+    /// Requests may arrive split across segments, and are gathered here until complete
+    request: [u8; REQUEST_BUFFER],
+    received: usize,
+    /// Length of the request last answered, removed before the next is parsed
+    consumed: usize,
+    /// Response still being sent, and how much of it has been
+    sending: Option<(http::Response<'static>, Body, usize)>,
+    /// Whether to wait for another request once the response is sent
+    keep_alive: bool,
+}
 
-impl embedded_hal::blocking::delay::DelayMs<u8> for NopDelay {
-    fn delay_ms(&mut self, ms: u8) {
-        cortex_m::asm::delay(u32::from(ms) * CPU_HZ / 1000);
+impl Connection {
+    fn new(handle: SocketHandle) -> Self {
+        Self {
+            handle,
+            request: [0; REQUEST_BUFFER],
+            received: 0,
+            consumed: 0,
+            sending: None,
+            keep_alive: false,
+        }
+    }
+
+    /// Send a response, as much of it as the socket has room for now and the rest from `send`
+    fn respond(&mut self, socket: &mut TcpSocket, head: http::Response<'static>, body: Body) {
+        self.sending = Some((head, body, 0));
+        self.send(socket);
+    }
+
+    /// Send more of the response, finishing once all of it is sent
+    ///
+    /// The response is formatted again each time, leaving out what was already sent, so nothing
+    /// needs buffering and nothing is cut short when the socket fills up.
+    fn send(&mut self, socket: &mut TcpSocket) {
+        let (head, body, sent) = match &mut self.sending {
+            Some(sending) => sending,
+            None => return,
+        };
+
+        let mut resume = Resume {
+            socket: &mut *socket,
+            skip: *sent,
+            sent: 0,
+        };
+        let done = write!(resume, "{}", head)
+            .and_then(|()| match body {
+                Body::Empty => Ok(()),
+                Body::Bytes(bytes) => resume.write_bytes(bytes),
+                Body::Json(json) => write!(resume, "{}", json),
+            })
+            .is_ok();
+        *sent += resume.sent;
+
+        if done {
+            self.sending = None;
+            self.finish(socket);
+        }
+    }
+
+    /// Close the connection after a response, unless the client asked to keep it open
+    fn finish(&self, socket: &mut TcpSocket) {
+        if !self.keep_alive {
+            socket.close();
+        }
     }
 }
 
-#[derive(Debug)]
-struct Request<'a> {
-    method: &'a str,
-    route: &'a str,
-    headers: Vec<(&'a str, &'a str), U16>,
-    body: Option<&'a str>,
+/// What follows a response head
+enum Body {
+    Empty,
+    /// Sent as it is
+    Bytes(&'static [u8]),
+    Json(Json),
 }
 
-impl<'a> Request<'a> {
-    fn from_str(input: &'a str) -> Result<Self, ()> {
-        let mut request = input.split("\n\n");
-        let mut head = request.next().expect("Couldn't get HTTP headers").lines();
-        let mut metadata = head.next().expect("Couldn't get first line").split(" ");
-        let method = metadata.next().expect("Couldn't get method");
-        let route = metadata.next().expect("Couldn't get route");
-        let headers = head
-            .filter(|s| s.contains(":"))
-            .map(|raw| {
-                let mut iter = raw.split(": ");
-                let key = iter.next().expect("Couldn't get key");
-                let value = iter.next().expect("Couldn't get value");
-
-                (key, value)
-            })
-            .collect();
+/// Writes to a socket, leaving out the first `skip` bytes and failing once the socket is full
+struct Resume<'s, 'a> {
+    socket: &'s mut TcpSocket<'a>,
+    skip: usize,
+    /// Bytes actually sent
+    sent: usize,
+}
+
+impl<'s, 'a> Resume<'s, 'a> {
+    fn write_bytes(&mut self, bytes: &[u8]) -> fmt::Result {
+        let skipped = self.skip.min(bytes.len());
+        self.skip -= skipped;
+        let rest = &bytes[skipped..];
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let sent = self.socket.send_slice(rest).map_err(|_| fmt::Error)?;
+        self.sent += sent;
+        match sent == rest.len() {
+            true => Ok(()),
+            false => Err(fmt::Error),
+        }
+    }
+}
+
+impl<'s, 'a> Write for Resume<'s, 'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes())
+    }
+}
+
+/// What answers a request, looked up by method and path in `ROUTES`
+#[derive(Clone, Copy)]
+enum Endpoint {
+    Index,
+    Status,
+    Hall,
+    Panic,
+    Identify,
+    IdentifySave,
+    HallSave,
+    /// Changes the setpoint or a setting, and answers with the status
+    Control,
+}
+
+const fn get(path: &'static str, endpoint: Endpoint) -> Route<Endpoint> {
+    Route {
+        method: Method::Get,
+        path,
+        handler: endpoint,
+    }
+}
+
+const fn post(path: &'static str, endpoint: Endpoint) -> Route<Endpoint> {
+    Route {
+        method: Method::Post,
+        path,
+        handler: endpoint,
+    }
+}
+
+/// Body of a JSON response, taken before anything is sent so that its length is known
+enum Json {
+    Status(Status),
+    Hall(Option<Result<HallTable, HallDetectError>>),
+    Panic(Option<panic::Record>),
+    Identify(Option<Result<MotorParameters, IdentifyError>>, f32),
+    /// Answered with the given status instead of 200
+    Error(u16, &'static str),
+}
 
-        let body = request.next();
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Status(status) => status.write(f),
+            Json::Hall(Some(Ok(table))) => {
+                write!(f, "{{\r\n\t\"table\": [")?;
+                for (code, state) in table.0.iter().enumerate() {
+                    if code > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match state {
+                        Some(state) => write!(f, "\"{:?}\"", state)?,
+                        None => write!(f, "null")?,
+                    }
+                }
+                write!(f, "]\r\n}}\r\n")
+            }
+            Json::Hall(Some(Err(e))) => write_error(f, e.description()),
+            Json::Hall(None) => write!(f, "{{\r\n\t\"table\": null\r\n}}\r\n"),
+            Json::Panic(Some(record)) => write!(
+                f,
+                "{{\r\n\t\"panic\": \"{}\"\r\n}}\r\n",
+                Escape(record.message())
+            ),
+            Json::Panic(None) => write!(f, "{{\r\n\t\"panic\": null\r\n}}\r\n"),
+            Json::Identify(result, erpm) => write_identify(f, *result, *erpm),
+            Json::Error(_, error) => write_error(f, error),
+        }
+    }
+}
+
+impl Json {
+    fn status(&self) -> u16 {
+        match self {
+            Json::Error(status, _) => *status,
+            _ => 200,
+        }
+    }
+}
+
+fn write_error<W: Write>(w: &mut W, error: &str) -> fmt::Result {
+    write!(w, "{{\r\n\t\"error\": \"{}\"\r\n}}\r\n", error)
+}
+
+struct Status {
+    /// Setpoint as commanded
+    control: ControlState,
+    /// Setpoint after ramping, as currently driven
+    applied: ControlState,
+    samples: Samples,
+    erpm: f32,
+    temperatures: Temperatures,
+    fault: Option<Fault>,
+    fault_history: [Option<Fault>; fault::HISTORY],
+    /// Why the controller last started
+    reset: ResetCause,
+    /// Milliseconds since then
+    uptime: u64,
+    /// Fraction of the last second spent awake
+    cpu_load: f32,
+    /// Deadman timeouts, and the time left on the setpoint being driven
+    command_timeout: Option<f32>,
+    throttle_timeout: Option<f32>,
+    remaining: Option<f32>,
+    armed: bool,
+    /// Gate driver overcurrent or overtemperature warning
+    gate_warning: bool,
+    /// Why the request this answers was refused, if it was
+    rejected: Option<Rejection>,
+}
+
+impl Status {
+    fn write<W: Write>(&self, w: &mut W) -> fmt::Result {
+        write!(
+            w,
+            "{{\r\n\t\"state\": \"{:?}\",\r\n\t\"applied\": \"{:?}\",\r\n\t\"erpm\": {:.0},\r\n\t\
+             \"current\": [{:.2}, {:.2}, {:.2}],\r\n\t\"bus_voltage\": {:.2},\r\n\t\
+             \"temperature\": {{\"fet\": {}, \"motor\": {}}},\r\n\t\"derate\": {:.2},\r\n\t\
+             \"fault\": ",
+            self.control,
+            self.applied,
+            self.erpm,
+            self.samples.phase_current[0],
+            self.samples.phase_current[1],
+            self.samples.phase_current[2],
+            self.samples.bus_voltage,
+            Number(self.temperatures.fet),
+            Number(self.temperatures.motor),
+            derate(&self.temperatures),
+        )?;
+
+        match self.fault {
+            Some(fault) => write!(w, "\"{}\"", fault.description())?,
+            None => write!(w, "null")?,
+        }
+
+        write!(w, ",\r\n\t\"fault_history\": [")?;
+        for (i, fault) in self.fault_history.iter().flatten().enumerate() {
+            if i > 0 {
+                write!(w, ", ")?;
+            }
+            write!(w, "\"{}\"", fault.description())?;
+        }
+        write!(
+            w,
+            "],\r\n\t\"reset\": \"{}\",\r\n\t\"uptime_ms\": {},\r\n\t\"cpu_load\": {:.2},\r\n\t\
+             \"timeout\": {{\"command\": {}, \"throttle\": {}, \"remaining\": {}}},\r\n\t\
+             \"armed\": {},\r\n\t\"gate_warning\": {},\r\n\t\"rejected\": ",
+            self.reset.description(),
+            self.uptime,
+            self.cpu_load,
+            Number(self.command_timeout),
+            Number(self.throttle_timeout),
+            Number(self.remaining),
+            self.armed,
+            self.gate_warning,
+        )?;
+
+        match self.rejected {
+            Some(reason) => write!(w, "\"{}\"", reason.description())?,
+            None => write!(w, "null")?,
+        }
+        write!(w, "\r\n}}\r\n")
+    }
+}
+
+fn write_identify<W: Write>(
+    w: &mut W,
+    result: Option<Result<MotorParameters, IdentifyError>>,
+    erpm: f32,
+) -> fmt::Result {
+    match result {
+        Some(Ok(parameters)) => write!(
+            w,
+            "{{\r\n\t\"resistance\": {:e},\r\n\t\"inductance\": {:e},\r\n\t\
+             \"flux_linkage\": {:e},\r\n\t\"erpm\": {:.0},\r\n\t\"rpm\": {:.0}\r\n}}\r\n",
+            parameters.resistance,
+            parameters.inductance,
+            parameters.flux_linkage,
+            erpm,
+            erpm / config::MOTOR.pole_pairs as f32,
+        ),
+        Some(Err(e)) => write!(w, "{{\r\n\t\"error\": \"{}\"\r\n}}\r\n", e.description()),
+        None => write!(w, "{{\r\n\t\"resistance\": null\r\n}}\r\n"),
+    }
+}
+
+/// Fraction of the requested current or duty cycle allowed at the measured temperatures
+///
+/// Nothing is allowed while either temperature is unknown, unless the motor has no thermistor.
+fn derate(temperatures: &Temperatures) -> f32 {
+    let fet = temperatures
+        .fet
+        .map_or(0.0, |t| config::FET_DERATING.factor(t));
+    let motor = match config::LIMITS.max_motor_temperature {
+        Some(_) => temperatures
+            .motor
+            .map_or(0.0, |t| config::MOTOR_DERATING.factor(t)),
+        None => 1.0,
+    };
+    fet.min(motor)
+}
+
+/// Derive the FOC current loop gains from identified motor parameters
+fn tune_current_loops(foc: &mut Foc, parameters: &MotorParameters) {
+    let (kp, ki) = parameters.current_gains(config::MOTOR.foc_bandwidth);
+    for pid in [&mut foc.d, &mut foc.q].iter_mut() {
+        pid.kp = kp;
+        pid.ki = ki;
+    }
+}
+
+/// Everything kept in flash across resets
+#[derive(Default)]
+struct Settings {
+    parameters: Option<MotorParameters>,
+    hall: Option<HallTable>,
+}
+
+impl Settings {
+    /// Bits of the first stored word, marking which of the rest hold a setting
+    const PARAMETERS: u32 = 1 << 0;
+    const HALL: u32 = 1 << 1;
+
+    /// Read the stored settings, with none set if nothing valid is stored
+    fn load(storage: &Flash) -> Self {
+        let mut words = [0; 5];
+        if !storage.read(&mut words) {
+            return Self::default();
+        }
+
+        Self {
+            parameters: match words[0] & Self::PARAMETERS {
+                0 => None,
+                _ => Some(MotorParameters::from_words(&[words[1], words[2], words[3]])),
+            },
+            hall: match words[0] & Self::HALL {
+                0 => None,
+                _ => HallTable::from_word(words[4]),
+            },
+        }
+    }
+
+    fn to_words(&self) -> [u32; 5] {
+        let mut words = [0; 5];
+        if let Some(parameters) = self.parameters {
+            words[0] |= Self::PARAMETERS;
+            words[1..4].copy_from_slice(&parameters.to_words());
+        }
+        if let Some(table) = self.hall {
+            words[0] |= Self::HALL;
+            words[4] = table.to_word();
+        }
+        words
+    }
+}
+
+/// Split a route into its path and `key=value` query parameters
+fn split_query<'a>(route: &'a str) -> (&'a str, impl Iterator<Item = (&'a str, &'a str)> + 'a) {
+    let mut parts = route.splitn(2, '?');
+    let path = parts.next().unwrap_or(route);
+    let query = parts.next().unwrap_or("").split('&').filter_map(|pair| {
+        let mut pair = pair.splitn(2, '=');
+        Some((pair.next()?, pair.next()?))
+    });
+
+    (path, query)
+}
+
+/// Value of the query parameter `key` as a number
+fn param<'a>(mut query: impl Iterator<Item = (&'a str, &'a str)>, key: &str) -> Option<f32> {
+    query
+        .find(|(k, _)| *k == key)
+        .and_then(|(_, value)| number(value))
+}
+
+/// A query parameter value as a finite number
+fn number(value: &str) -> Option<f32> {
+    value.parse().ok().filter(|value: &f32| value.is_finite())
+}
+
+/// Drive the phases from the hall sensor position, floating them if the sensor reads garbage
+fn commutate_hall(driver: &mut Driver, hall: &Hall, direction: bool) {
+    match hall.commutation(direction) {
+        Some(state) => driver.commutate(state),
+        None => driver.set_idle(),
+    }
+}
+
+/// Writes a string with the characters that would end or break a JSON string escaped
+struct Escape<'a>(&'a str);
+
+impl<'a> fmt::Display for Escape<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                c if c < ' ' => (),
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a JSON number, or null for `None`
+struct Number(Option<f32>);
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(number) => write!(f, "{:.2}", number),
+            None => f.write_str("null"),
+        }
+    }
+}
+
+struct NopDelay;
+
+impl embedded_hal::blocking::delay::DelayMs<u8> for NopDelay {
+    fn delay_ms(&mut self, ms: u8) {
+        cortex_m::asm::delay(u32::from(ms) * CPU_HZ / 1000);
+    }
+}
 
-        Ok(Self {
-            method,
-            route,
-            headers,
-            body,
-        })
+impl embedded_hal::blocking::delay::DelayUs<u16> for NopDelay {
+    fn delay_us(&mut self, us: u16) {
+        cortex_m::asm::delay(u32::from(us) * (CPU_HZ / 1_000_000));
     }
 }
@@ -1,28 +1,106 @@
-use embedded_hal::digital::{OutputPin, StatefulOutputPin};
-
-pub struct MotorDriver<
-    AL: OutputPin + StatefulOutputPin,
-    AH: OutputPin + StatefulOutputPin,
-    BL: OutputPin + StatefulOutputPin,
-    BH: OutputPin + StatefulOutputPin,
-    CL: OutputPin + StatefulOutputPin,
-    CH: OutputPin + StatefulOutputPin,
-> {
-    pub a: Phase<AL, AH>,
-    pub b: Phase<BL, BH>,
-    pub c: Phase<CL, CH>,
+use {
+    crate::{hall::HallTable, pid::clamp},
+    core::f32::consts::PI,
+    embedded_hal::digital::{OutputPin, StatefulOutputPin},
+};
+
+/// Duty cycle used to hold the rotor in place while detecting the hall table
+const HALL_DETECT_DUTY: f32 = 0.05;
+/// Number of `HallDetector::update` calls to hold each vector before sampling
+const HALL_DETECT_HOLD: u32 = 32;
+/// Commutation states visited during detection, two electrical revolutions so the rotor has
+/// settled by the second
+const HALL_DETECT_STEPS: usize = 12;
+
+pub struct MotorDriver<A: PhaseDriver, B: PhaseDriver, C: PhaseDriver> {
+    pub a: A,
+    pub b: B,
+    pub c: C,
     pub comm_state: CommutationState,
+    pub duty: f32,
+}
+
+/// One half-bridge of the inverter
+pub trait PhaseDriver {
+    /// Turn off both gates
+    fn set_floating(&mut self);
+
+    /// Connect the phase to VIN, modulated by the current duty cycle
+    fn set_high(&mut self);
+
+    /// Set the phase to ground
+    fn set_low(&mut self);
+
+    /// Set the fraction of each switching period spent high, from 0.0 to 1.0
+    ///
+    /// Backends that cannot modulate the phase ignore this and switch fully on.
+    fn set_duty(&mut self, _duty: f32) {}
+
+    /// Switch only the low side at the current duty cycle, leaving the high side off
+    ///
+    /// Backends that cannot modulate the phase hold the low side on instead.
+    fn set_low_chopped(&mut self) {
+        self.set_low();
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum ControlState {
     Idle,
-    Brake,
+    Brake(BrakeMode),
     Forward,
     Reverse,
+    /// Six-step drive at the given duty cycle, negative in reverse
+    Duty(f32),
+    /// Closed-loop speed control to the given electrical RPM, negative in reverse
+    Speed(f32),
+    /// Closed-loop current control to the given amps, negative for reverse torque
+    Current(f32),
+    /// Running `HallDetector`
+    DetectHall,
+    /// Running `Identify`
+    Identify,
+}
+
+impl ControlState {
+    /// Direction to commutate in, as passed to `MotorDriver::step`, while the motor is driven
+    ///
+    /// Under `Current` this is only the direction to start in, once the rotor is turning it keeps
+    /// being commutated the same way so that reverse torque regenerates.
+    pub fn direction(&self) -> Option<bool> {
+        match self {
+            ControlState::Forward => Some(false),
+            ControlState::Reverse => Some(true),
+            ControlState::Duty(duty) => Some(*duty < 0.0),
+            ControlState::Speed(erpm) => Some(*erpm < 0.0),
+            ControlState::Current(amps) => Some(*amps < 0.0),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BrakeMode {
+    /// All phases floating, the motor freewheels
+    Coast,
+    /// All phases shorted to ground through the low sides
+    Short,
+    /// Low sides chopped at the given duty cycle, limiting the braking current
+    Dynamic(f32),
 }
 
-#[derive(Debug)]
+/// How the driver decides when to move to the next commutation state
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Commutation {
+    /// Step at a fixed rate regardless of rotor position
+    OpenLoop,
+    /// Follow the rotor position reported by the hall sensors
+    Hall,
+    /// Follow back-EMF zero crossings on the floating phase
+    Sensorless,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CommutationState {
     AB,
     AC,
@@ -44,7 +122,7 @@ impl CommutationState {
         }
     }
 
-    fn previous(&self) -> Self {
+    pub fn previous(&self) -> Self {
         match self {
             CommutationState::AB => CommutationState::CB,
             CommutationState::AC => CommutationState::AB,
@@ -54,32 +132,83 @@ impl CommutationState {
             CommutationState::CB => CommutationState::CA,
         }
     }
+
+    /// Phases driven high, driven low and left floating, numbered 0 to 2 for A to C
+    pub fn phases(&self) -> (usize, usize, usize) {
+        match self {
+            CommutationState::AB => (0, 1, 2),
+            CommutationState::AC => (0, 2, 1),
+            CommutationState::BC => (1, 2, 0),
+            CommutationState::BA => (1, 0, 2),
+            CommutationState::CA => (2, 0, 1),
+            CommutationState::CB => (2, 1, 0),
+        }
+    }
+
+    /// Electrical angle of the stator field in radians, with phase A along zero
+    pub fn angle(&self) -> f32 {
+        match self {
+            CommutationState::AB => -PI / 6.0,
+            CommutationState::AC => PI / 6.0,
+            CommutationState::BC => PI / 2.0,
+            CommutationState::BA => 5.0 * PI / 6.0,
+            CommutationState::CA => -5.0 * PI / 6.0,
+            CommutationState::CB => -PI / 2.0,
+        }
+    }
+
+    /// Move one step, using `next` when `direction` is true
+    pub fn advance(&self, direction: bool) -> Self {
+        match direction {
+            true => self.next(),
+            false => self.previous(),
+        }
+    }
 }
 
-impl<
-        AL: OutputPin + StatefulOutputPin,
-        AH: OutputPin + StatefulOutputPin,
-        BL: OutputPin + StatefulOutputPin,
-        BH: OutputPin + StatefulOutputPin,
-        CL: OutputPin + StatefulOutputPin,
-        CH: OutputPin + StatefulOutputPin,
-    > MotorDriver<AL, AH, BL, BH, CL, CH>
-{
-    pub fn new(a: Phase<AL, AH>, b: Phase<BL, BH>, c: Phase<CL, CH>) -> Self {
-        Self {
+impl<A: PhaseDriver, B: PhaseDriver, C: PhaseDriver> MotorDriver<A, B, C> {
+    pub fn new(a: A, b: B, c: C) -> Self {
+        let mut driver = Self {
             a,
             b,
             c,
             comm_state: CommutationState::AB,
-        }
+            duty: 0.0,
+        };
+        driver.set_idle();
+        driver.set_duty(0.0);
+        driver
+    }
+
+    /// Set the duty cycle applied to the high side of each commutation step
+    pub fn set_duty(&mut self, duty: f32) {
+        let duty = clamp(duty, 0.0, 1.0);
+        self.duty = duty;
+        self.apply_duty(duty);
+    }
+
+    fn apply_duty(&mut self, duty: f32) {
+        self.a.set_duty(duty);
+        self.b.set_duty(duty);
+        self.c.set_duty(duty);
     }
 
     pub fn step(&mut self, direction: bool) {
-        self.comm_state = match direction {
-            true => self.comm_state.next(),
-            false => self.comm_state.previous(),
-        };
-        //self.comm_state = self.comm_state.next();
+        self.commutate(self.comm_state.advance(direction));
+    }
+
+    /// Drive the phases for the given commutation state
+    pub fn commutate(&mut self, state: CommutationState) {
+        self.hold(state, self.duty);
+    }
+
+    /// Drive the phases for the given commutation state at a duty cycle other than `self.duty`
+    ///
+    /// The next call to `commutate` or `step` goes back to `self.duty`.
+    pub fn hold(&mut self, state: CommutationState, duty: f32) {
+        self.apply_duty(duty);
+
+        self.comm_state = state;
 
         match self.comm_state {
             CommutationState::AB => {
@@ -115,11 +244,130 @@ impl<
         }
     }
 
+    /// Switch every phase at its own duty cycle, for sinusoidal and space-vector drive
+    pub fn set_duties(&mut self, duties: [f32; 3]) {
+        self.a.set_duty(duties[0]);
+        self.b.set_duty(duties[1]);
+        self.c.set_duty(duties[2]);
+
+        self.a.set_high();
+        self.b.set_high();
+        self.c.set_high();
+    }
+
     pub fn set_idle(&mut self) {
         self.a.set_floating();
         self.b.set_floating();
         self.c.set_floating();
     }
+
+    pub fn brake(&mut self, mode: BrakeMode) {
+        match mode {
+            BrakeMode::Coast => self.set_idle(),
+            BrakeMode::Short => {
+                self.a.set_low();
+                self.b.set_low();
+                self.c.set_low();
+            }
+            BrakeMode::Dynamic(duty) => {
+                self.apply_duty(clamp(duty, 0.0, 1.0));
+
+                self.a.set_low_chopped();
+                self.b.set_low_chopped();
+                self.c.set_low_chopped();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HallDetectError {
+    /// Two different rotor positions produced the same code
+    Repeated,
+    /// A code of 0b000 or 0b111 was seen, so a sensor is unpowered or unplugged
+    Disconnected,
+}
+
+impl HallDetectError {
+    pub fn description(&self) -> &'static str {
+        match self {
+            HallDetectError::Repeated => "hall code repeated",
+            HallDetectError::Disconnected => "sensor disconnected",
+        }
+    }
+}
+
+/// Builds a `HallTable` by holding the rotor at each commutation state and recording the code
+pub struct HallDetector {
+    step: usize,
+    ticks: u32,
+    codes: [u8; 6],
+}
+
+impl HallDetector {
+    pub const fn new() -> Self {
+        Self {
+            step: 0,
+            ticks: 0,
+            codes: [0; 6],
+        }
+    }
+
+    /// Advance detection by one tick, returning the result once every state has been visited
+    ///
+    /// Leaves the driver idle when finished.
+    pub fn update<A: PhaseDriver, B: PhaseDriver, C: PhaseDriver>(
+        &mut self,
+        driver: &mut MotorDriver<A, B, C>,
+        code: u8,
+    ) -> Option<Result<HallTable, HallDetectError>> {
+        if self.step == 0 && self.ticks == 0 {
+            driver.hold(CommutationState::AB, HALL_DETECT_DUTY);
+        }
+
+        self.ticks += 1;
+        if self.ticks < HALL_DETECT_HOLD {
+            return None;
+        }
+        self.ticks = 0;
+
+        if self.step >= HALL_DETECT_STEPS - 6 {
+            self.codes[self.step + 6 - HALL_DETECT_STEPS] = code;
+        }
+        self.step += 1;
+
+        if self.step < HALL_DETECT_STEPS {
+            driver.hold(driver.comm_state.next(), HALL_DETECT_DUTY);
+            return None;
+        }
+
+        driver.set_idle();
+        self.step = 0;
+
+        Some(self.table())
+    }
+
+    /// Validate the recorded codes, which were taken in `CommutationState::next` order from AB
+    fn table(&self) -> Result<HallTable, HallDetectError> {
+        let mut table = HallTable([None; 8]);
+        let mut state = CommutationState::AB;
+
+        for &code in self.codes.iter() {
+            if code == 0b000 || code == 0b111 {
+                return Err(HallDetectError::Disconnected);
+            }
+
+            let entry = &mut table.0[usize::from(code)];
+            if entry.is_some() {
+                return Err(HallDetectError::Repeated);
+            }
+            *entry = Some(state);
+
+            state = state.next();
+        }
+
+        Ok(table)
+    }
 }
 
 pub struct Phase<L: OutputPin + StatefulOutputPin, H: OutputPin + StatefulOutputPin> {
@@ -138,6 +386,21 @@ impl<L: OutputPin + StatefulOutputPin, H: OutputPin + StatefulOutputPin> Phase<L
         }
     }
 
+    /*
+    fn is_set_high(&mut self) -> bool {
+        self.high_gate.is_set_high() && self.low_gate.is_set_low()
+    }
+
+    fn is_set_low(&mut self) -> bool {
+        self.low_gate.is_set_high() && self.high_gate.is_set_low()
+    }
+    */
+}
+
+/// Plain GPIO gates, so the phase is only ever fully on or fully off
+impl<L: OutputPin + StatefulOutputPin, H: OutputPin + StatefulOutputPin> PhaseDriver
+    for Phase<L, H>
+{
     fn set_floating(&mut self) {
         self.high_gate.set_low();
         self.low_gate.set_low();
@@ -155,14 +418,4 @@ impl<L: OutputPin + StatefulOutputPin, H: OutputPin + StatefulOutputPin> Phase<L
         self.high_gate.set_low();
         self.low_gate.set_high();
     }
-
-    /*
-    fn is_set_high(&mut self) -> bool {
-        self.high_gate.is_set_high() && self.low_gate.is_set_low()
-    }
-
-    fn is_set_low(&mut self) -> bool {
-        self.low_gate.is_set_high() && self.high_gate.is_set_low()
-    }
-    */
 }
@@ -0,0 +1,116 @@
+//! NTC thermistor temperature conversion
+
+use core::f32::consts::LN_2;
+
+const KELVIN: f32 = 273.15;
+/// Divider outputs closer than this to either rail mean the thermistor is open or shorted
+const MIN_RATIO: f32 = 0.01;
+
+/// Relationship between thermistor resistance and temperature
+#[derive(Debug, Clone, Copy)]
+pub enum Model {
+    /// Resistance at 25°C in ohms and β, which is good enough over a narrow range
+    Beta { r25: f32, beta: f32 },
+    /// 1/T = a + b ln(R) + c ln(R)³, with T in kelvin and R in ohms
+    SteinhartHart { a: f32, b: f32, c: f32 },
+}
+
+/// Where the thermistor sits in its divider, with a fixed resistor in the other half
+#[derive(Debug, Clone, Copy)]
+pub enum Divider {
+    /// Thermistor from the output to ground, fixed resistor from the ADC reference
+    LowSide,
+    /// Thermistor from the ADC reference to the output, fixed resistor to ground
+    HighSide,
+}
+
+/// Thermistor in a divider across the ADC reference
+#[derive(Debug, Clone, Copy)]
+pub struct Ntc {
+    pub model: Model,
+    pub divider: Divider,
+    /// The fixed half of the divider, in ohms
+    pub resistor: f32,
+}
+
+impl Ntc {
+    /// Temperature in °C from the divider output as a fraction of the ADC reference
+    ///
+    /// `None` when the output is at either rail, as with the thermistor open, shorted or
+    /// unplugged.
+    pub fn temperature(&self, ratio: f32) -> Option<f32> {
+        // Written this way round so that NaN fails too
+        if !(ratio > MIN_RATIO && ratio < 1.0 - MIN_RATIO) {
+            return None;
+        }
+
+        let resistance = match self.divider {
+            Divider::LowSide => self.resistor * ratio / (1.0 - ratio),
+            Divider::HighSide => self.resistor * (1.0 - ratio) / ratio,
+        };
+
+        let inverse = match self.model {
+            Model::Beta { r25, beta } => ln(resistance / r25) / beta + 1.0 / (25.0 + KELVIN),
+            Model::SteinhartHart { a, b, c } => {
+                let ln_r = ln(resistance);
+                a + b * ln_r + c * ln_r * ln_r * ln_r
+            }
+        };
+
+        Some(1.0 / inverse - KELVIN)
+    }
+}
+
+/// Linear reduction of the allowed current between two temperatures, in °C
+#[derive(Debug, Clone, Copy)]
+pub struct Derating {
+    /// Full current up to here
+    pub start: f32,
+    /// No current from here
+    pub end: f32,
+}
+
+impl Derating {
+    /// Fraction of the requested current or duty cycle allowed at `temperature`
+    pub fn factor(&self, temperature: f32) -> f32 {
+        if temperature <= self.start {
+            1.0
+        } else if temperature >= self.end {
+            0.0
+        } else {
+            (self.end - temperature) / (self.end - self.start)
+        }
+    }
+}
+
+/// Temperatures from both thermistors, in °C, `None` for a faulty sensor
+#[derive(Debug, Clone, Copy)]
+pub struct Temperatures {
+    pub fet: Option<f32>,
+    pub motor: Option<f32>,
+}
+
+impl Temperatures {
+    pub const fn new() -> Self {
+        Self {
+            fet: None,
+            motor: None,
+        }
+    }
+}
+
+/// Natural logarithm, accurate to about 1e-5 for positive normal numbers
+pub fn ln(x: f32) -> f32 {
+    // x = m * 2^e with m in [1, 2)
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127;
+    let mantissa = f32::from_bits((bits & 0x007f_ffff) | 0x3f80_0000);
+
+    // ln(m) = 2 atanh((m - 1) / (m + 1))
+    let y = (mantissa - 1.0) / (mantissa + 1.0);
+    let y2 = y * y;
+    let series =
+        y * (2.0 + y2 * (2.0 / 3.0 + y2 * (2.0 / 5.0 + y2 * (2.0 / 7.0 + y2 * 2.0 / 9.0))));
+
+    exponent as f32 * LN_2 + series
+}
@@ -0,0 +1,46 @@
+//! Panic handler that floats the phases and resets, leaving a record of the panic behind
+
+pub use crankshaft::crash::Record;
+
+use {
+    crate::board::{Board, Selected},
+    core::{
+        fmt::Write,
+        mem::MaybeUninit,
+        panic::PanicInfo,
+        ptr,
+        sync::atomic::{self, Ordering},
+    },
+    cortex_m::{interrupt, peripheral::SCB},
+};
+
+/// Left alone by the startup code, so it survives the reset
+#[link_section = ".uninit.PANIC"]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+/// The record left by a panic before the last reset, see `Record::report`
+///
+/// Not locked, so only call this from one context.
+pub fn report(head_only: bool) -> Option<Record> {
+    unsafe {
+        let mut record = ptr::read_volatile(RECORD.as_ptr());
+        let reported = record.report(head_only);
+        ptr::write_volatile(RECORD.as_mut_ptr(), record);
+        reported
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    interrupt::disable();
+    Selected::force_off();
+
+    let record = unsafe { &mut *RECORD.as_mut_ptr() };
+    record.begin();
+    write!(record, "{}", info).ok();
+    record.finish();
+
+    // Make sure the record is in RAM before resetting
+    atomic::compiler_fence(Ordering::SeqCst);
+    SCB::sys_reset()
+}
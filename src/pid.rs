@@ -0,0 +1,89 @@
+/// Name passed to `Pid::set` that is not one of its gains or limits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnknownGain;
+
+/// PID controller with a clamped integrator
+#[derive(Debug, Clone, Copy)]
+pub struct Pid {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// Bound on the magnitude of the integral term, in output units
+    pub integral_limit: f32,
+    pub output_min: f32,
+    pub output_max: f32,
+    integral: f32,
+    previous_error: f32,
+}
+
+impl Pid {
+    pub const fn new(
+        kp: f32,
+        ki: f32,
+        kd: f32,
+        integral_limit: f32,
+        output_min: f32,
+        output_max: f32,
+    ) -> Self {
+        Self {
+            kp,
+            ki,
+            kd,
+            integral_limit,
+            output_min,
+            output_max,
+            integral: 0.0,
+            previous_error: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.previous_error = 0.0;
+    }
+
+    /// Change a gain or limit by name, as used in the HTTP API
+    pub fn set(&mut self, name: &str, value: f32) -> Result<(), UnknownGain> {
+        match name {
+            "kp" => self.kp = value,
+            "ki" => self.ki = value,
+            "kd" => self.kd = value,
+            "integral_limit" => self.integral_limit = value,
+            "output_min" => self.output_min = value,
+            "output_max" => self.output_max = value,
+            _ => return Err(UnknownGain),
+        }
+
+        Ok(())
+    }
+
+    /// Advance the controller by `dt` seconds
+    pub fn update(&mut self, setpoint: f32, measured: f32, dt: f32) -> f32 {
+        let error = setpoint - measured;
+
+        self.integral = clamp(
+            self.integral + self.ki * error * dt,
+            -self.integral_limit,
+            self.integral_limit,
+        );
+
+        let derivative = (error - self.previous_error) / dt;
+        self.previous_error = error;
+
+        clamp(
+            self.kp * error + self.integral + self.kd * derivative,
+            self.output_min,
+            self.output_max,
+        )
+    }
+}
+
+pub fn clamp(value: f32, min: f32, max: f32) -> f32 {
+    if value < min {
+        min
+    } else if value > max {
+        max
+    } else {
+        value
+    }
+}
@@ -0,0 +1,232 @@
+//! Complementary PWM on TIM1 driving the three half-bridges
+
+use {
+    core::marker::PhantomData,
+    crankshaft::motor::PhaseDriver,
+    stm32f4xx_hal::{
+        gpio::{
+            gpioa::{PA10, PA8, PA9},
+            gpiob::{PB13, PB14, PB15},
+            Alternate, AF1,
+        },
+        rcc::Clocks,
+        stm32::{GPIOA, GPIOB, RCC, TIM1},
+        time::Hertz,
+    },
+};
+
+/// High side on while the counter is below the compare value
+const OCM_PWM_MODE_1: u8 = 0b110;
+/// Active while the counter is at or above the compare value
+const OCM_PWM_MODE_2: u8 = 0b111;
+/// Reference held inactive, so only the low side conducts
+const OCM_FORCE_INACTIVE: u8 = 0b100;
+
+pub type Pins = (
+    PA8<Alternate<AF1>>,
+    PA9<Alternate<AF1>>,
+    PA10<Alternate<AF1>>,
+    PB13<Alternate<AF1>>,
+    PB14<Alternate<AF1>>,
+    PB15<Alternate<AF1>>,
+);
+
+pub struct C1;
+pub struct C2;
+pub struct C3;
+
+/// One TIM1 channel and its complementary output driving a half-bridge
+pub struct PwmPhase<C> {
+    _channel: PhantomData<C>,
+}
+
+/// Configure TIM1 for center-aligned complementary PWM and split it into its three phases
+///
+/// The timer is assumed to run from an undivided PCLK2, as configured in `init`. All outputs are
+/// left floating with a duty cycle of zero.
+pub fn tim1(
+    tim: TIM1,
+    _pins: Pins,
+    clocks: Clocks,
+    freq: Hertz,
+    dead_time_ns: u32,
+) -> (PwmPhase<C1>, PwmPhase<C2>, PwmPhase<C3>) {
+    start(tim, clocks, freq, dead_time_ns);
+
+    (
+        PwmPhase {
+            _channel: PhantomData,
+        },
+        PwmPhase {
+            _channel: PhantomData,
+        },
+        PwmPhase {
+            _channel: PhantomData,
+        },
+    )
+}
+
+/// Run TIM1 only for its ADC trigger and update interrupt, on boards whose gates are not on its
+/// pins
+pub fn timebase(tim: TIM1, clocks: Clocks, freq: Hertz) {
+    start(tim, clocks, freq, 0);
+}
+
+fn start(tim: TIM1, clocks: Clocks, freq: Hertz, dead_time_ns: u32) {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.apb2enr.modify(|_, w| w.tim1en().set_bit());
+    rcc.apb2rstr.modify(|_, w| w.tim1rst().set_bit());
+    rcc.apb2rstr.modify(|_, w| w.tim1rst().clear_bit());
+
+    let tim_clk = clocks.pclk2().0;
+
+    // Counts up then down, so a full period is twice the auto-reload value
+    let arr = tim_clk / freq.0 / 2;
+    tim.psc.write(|w| unsafe { w.psc().bits(0) });
+    tim.arr.write(|w| unsafe { w.arr().bits(arr as u16) });
+
+    tim.ccmr1_output.write(|w| unsafe {
+        w.oc1m()
+            .bits(OCM_FORCE_INACTIVE)
+            .oc1pe()
+            .set_bit()
+            .oc2m()
+            .bits(OCM_FORCE_INACTIVE)
+            .oc2pe()
+            .set_bit()
+    });
+    tim.ccmr2_output.write(|w| unsafe {
+        w.oc3m()
+            .bits(OCM_FORCE_INACTIVE)
+            .oc3pe()
+            .set_bit()
+            .oc4m()
+            .bits(OCM_PWM_MODE_2)
+            .oc4pe()
+            .set_bit()
+    });
+    tim.ccr1.write(|w| unsafe { w.ccr1().bits(0) });
+    tim.ccr2.write(|w| unsafe { w.ccr2().bits(0) });
+    tim.ccr3.write(|w| unsafe { w.ccr3().bits(0) });
+    // Channel 4 has no pin and only triggers the ADC, rising just before the top of the count
+    // where every low side is on
+    tim.ccr4
+        .write(|w| unsafe { w.ccr4().bits((arr - 1) as u16) });
+
+    // All phases disabled: with OSSR set they drive their inactive (low) level, so both gates of
+    // every phase are off
+    tim.ccer.write(|w| w.cc4e().set_bit());
+
+    let ticks = (u64::from(dead_time_ns) * u64::from(tim_clk) + 999_999_999) / 1_000_000_000;
+    tim.bdtr.write(|w| unsafe {
+        w.dtg()
+            .bits(dead_time_bits(ticks as u32))
+            .ossr()
+            .set_bit()
+            .ossi()
+            .set_bit()
+            .moe()
+            .set_bit()
+    });
+
+    // One update event per period rather than at both ends of the count
+    tim.rcr.write(|w| unsafe { w.rep().bits(1) });
+
+    // Load the prescaler and shadow registers before starting
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.cr1
+        .write(|w| unsafe { w.cms().bits(0b01).arpe().set_bit().cen().set_bit() });
+}
+
+/// Raise TIM1_UP_TIM10 once every PWM period
+pub fn listen_update() {
+    let tim = unsafe { &(*TIM1::ptr()) };
+    tim.sr.modify(|_, w| w.uif().clear_bit());
+    tim.dier.modify(|_, w| w.uie().set_bit());
+}
+
+/// Acknowledge the update interrupt
+pub fn clear_update() {
+    let tim = unsafe { &(*TIM1::ptr()) };
+    tim.sr.modify(|_, w| w.uif().clear_bit());
+}
+
+/// Force all six gate pins low as plain outputs, for when nothing that owns them can be trusted
+pub fn force_off() {
+    let tim = unsafe { &(*TIM1::ptr()) };
+    tim.bdtr.modify(|_, w| w.moe().clear_bit());
+
+    // Each pin is set low before it becomes an output, so it is never driven high
+    let gpioa = unsafe { &(*GPIOA::ptr()) };
+    gpioa.bsrr.write(|w| unsafe { w.bits(0b111 << (8 + 16)) });
+    gpioa.moder.modify(|r, w| unsafe {
+        w.bits(r.bits() & !(0b11_11_11 << (8 * 2)) | (0b01_01_01 << (8 * 2)))
+    });
+    let gpiob = unsafe { &(*GPIOB::ptr()) };
+    gpiob.bsrr.write(|w| unsafe { w.bits(0b111 << (13 + 16)) });
+    gpiob.moder.modify(|r, w| unsafe {
+        w.bits(r.bits() & !(0b11_11_11 << (13 * 2)) | (0b01_01_01 << (13 * 2)))
+    });
+}
+
+/// Encode a dead time in timer clock ticks into the BDTR DTG field, rounding up
+fn dead_time_bits(ticks: u32) -> u8 {
+    if ticks <= 127 {
+        ticks as u8
+    } else if ticks <= 254 {
+        0x80 | ((ticks + 1) / 2 - 64) as u8
+    } else if ticks <= 504 {
+        0xC0 | ((ticks + 7) / 8 - 32) as u8
+    } else if ticks <= 1008 {
+        0xE0 | ((ticks + 15) / 16 - 32) as u8
+    } else {
+        0xFF
+    }
+}
+
+macro_rules! phase {
+    ($C:ident, $ccmr:ident, $ocm:ident, $ccr:ident, $cce:ident, $ccne:ident) => {
+        impl PhaseDriver for PwmPhase<$C> {
+            fn set_floating(&mut self) {
+                let tim = unsafe { &(*TIM1::ptr()) };
+                tim.ccer
+                    .modify(|_, w| w.$cce().clear_bit().$ccne().clear_bit());
+            }
+
+            /// Switch between VIN and ground, with the low side as synchronous rectifier
+            fn set_high(&mut self) {
+                let tim = unsafe { &(*TIM1::ptr()) };
+                tim.$ccmr
+                    .modify(|_, w| unsafe { w.$ocm().bits(OCM_PWM_MODE_1) });
+                tim.ccer.modify(|_, w| w.$cce().set_bit().$ccne().set_bit());
+            }
+
+            fn set_low(&mut self) {
+                let tim = unsafe { &(*TIM1::ptr()) };
+                tim.$ccmr
+                    .modify(|_, w| unsafe { w.$ocm().bits(OCM_FORCE_INACTIVE) });
+                tim.ccer.modify(|_, w| w.$cce().set_bit().$ccne().set_bit());
+            }
+
+            /// With CCxE cleared the high side stays off and OCxN follows the PWM reference
+            fn set_low_chopped(&mut self) {
+                let tim = unsafe { &(*TIM1::ptr()) };
+                tim.$ccmr
+                    .modify(|_, w| unsafe { w.$ocm().bits(OCM_PWM_MODE_1) });
+                tim.ccer
+                    .modify(|_, w| w.$cce().clear_bit().$ccne().set_bit());
+            }
+
+            fn set_duty(&mut self, duty: f32) {
+                let tim = unsafe { &(*TIM1::ptr()) };
+                let arr = f32::from(tim.arr.read().arr().bits());
+                tim.$ccr
+                    .write(|w| unsafe { w.$ccr().bits((duty * arr) as u16) });
+            }
+        }
+    };
+}
+
+phase!(C1, ccmr1_output, oc1m, ccr1, cc1e, cc1ne);
+phase!(C2, ccmr1_output, oc2m, ccr2, cc2e, cc2ne);
+phase!(C3, ccmr2_output, oc3m, ccr3, cc3e, cc3ne);
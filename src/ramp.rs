@@ -0,0 +1,118 @@
+//! Slew rate limiting between the commanded and applied `ControlState`
+
+use crate::motor::{BrakeMode, ControlState};
+
+/// Largest change per second of each kind of setpoint
+#[derive(Debug, Clone, Copy)]
+pub struct Rates {
+    pub duty: f32,
+    /// Amps per second
+    pub current: f32,
+    /// eRPM per second
+    pub speed: f32,
+}
+
+pub struct Ramp {
+    /// What `ControlState::Forward` stands for, `Reverse` being the same in the other direction
+    forward: ControlState,
+    rates: Rates,
+    /// Applied while waiting for the rotor to stop before driving it the other way
+    reversal: BrakeMode,
+    applied: ControlState,
+}
+
+impl Ramp {
+    pub const fn new(forward: ControlState, rates: Rates, reversal: BrakeMode) -> Self {
+        Self {
+            forward,
+            rates,
+            reversal,
+            applied: ControlState::Idle,
+        }
+    }
+
+    pub fn applied(&self) -> ControlState {
+        self.applied
+    }
+
+    /// Move the applied setpoint towards `commanded` by one step of `dt` seconds
+    ///
+    /// Duty cycle and speed setpoints ramp down to zero before changing sign, and then hold the
+    /// reversal brake until `erpm` reads zero. Current setpoints pass straight through zero, as
+    /// negative current regenerates rather than reversing. States without a setpoint apply
+    /// immediately.
+    pub fn update(&mut self, commanded: ControlState, erpm: f32, dt: f32) -> ControlState {
+        let target = match commanded {
+            ControlState::Forward => self.forward,
+            ControlState::Reverse => negate(self.forward),
+            commanded => commanded,
+        };
+
+        self.applied = match (self.applied, target) {
+            (ControlState::Duty(from), ControlState::Duty(to)) => self
+                .reversing(from, to, erpm, self.rates.duty * dt)
+                .map_or(self.brake(), ControlState::Duty),
+            (ControlState::Speed(from), ControlState::Speed(to)) => self
+                .reversing(from, to, erpm, self.rates.speed * dt)
+                .map_or(self.brake(), ControlState::Speed),
+            (ControlState::Current(from), ControlState::Current(to)) => {
+                ControlState::Current(slew(from, to, self.rates.current * dt))
+            }
+            // Start from standstill, or from the measured speed to take over smoothly as long as
+            // the rotor is not turning the other way
+            (_, ControlState::Duty(to)) => self
+                .reversing(0.0, to, erpm, self.rates.duty * dt)
+                .map_or(self.brake(), ControlState::Duty),
+            (_, ControlState::Speed(to)) => {
+                let from = if to * erpm < 0.0 { 0.0 } else { erpm };
+                self.reversing(from, to, erpm, self.rates.speed * dt)
+                    .map_or(self.brake(), ControlState::Speed)
+            }
+            (_, ControlState::Current(to)) => {
+                ControlState::Current(slew(0.0, to, self.rates.current * dt))
+            }
+            (_, target) => target,
+        };
+
+        self.applied
+    }
+
+    /// Step from `from` towards `to` without crossing zero, or `None` if the rotor has to stop
+    /// first
+    fn reversing(&self, from: f32, to: f32, erpm: f32, step: f32) -> Option<f32> {
+        if from == 0.0 && to * erpm < 0.0 {
+            return None;
+        }
+
+        let next = slew(from, to, step);
+        if from * next < 0.0 {
+            Some(0.0)
+        } else {
+            Some(next)
+        }
+    }
+
+    fn brake(&self) -> ControlState {
+        ControlState::Brake(self.reversal)
+    }
+}
+
+/// Step from `from` towards `to` by at most `step`
+fn slew(from: f32, to: f32, step: f32) -> f32 {
+    if to > from + step {
+        from + step
+    } else if to < from - step {
+        from - step
+    } else {
+        to
+    }
+}
+
+fn negate(state: ControlState) -> ControlState {
+    match state {
+        ControlState::Duty(duty) => ControlState::Duty(-duty),
+        ControlState::Speed(erpm) => ControlState::Speed(-erpm),
+        ControlState::Current(amps) => ControlState::Current(-amps),
+        state => state,
+    }
+}
@@ -0,0 +1,204 @@
+//! Six-step commutation from back-EMF zero crossings on the floating phase
+//!
+//! All timings are in PWM periods, the rate at which `Sensorless::update` is called.
+
+use crate::motor::CommutationState;
+
+/// Duty cycle used while aligning and ramping in open loop
+pub const STARTUP_DUTY: f32 = 0.1;
+
+/// Time the rotor is held at the first state before ramping
+const ALIGN_PERIODS: u32 = 10_000;
+/// Open-loop step length at the start and end of the ramp
+const RAMP_START_PERIOD: u32 = 1_000;
+const RAMP_END_PERIOD: u32 = 60;
+/// Open-loop steps to attempt before giving up on synchronising
+const RAMP_MAX_STEPS: u32 = 600;
+/// Consecutive steps with a zero crossing needed to hand over to closed loop
+const SYNC_CROSSINGS: u32 = 12;
+/// Periods ignored after each commutation while the floating phase demagnetises
+const MIN_BLANKING: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Stopped,
+    /// Holding the first state so the rotor starts from a known position
+    Align,
+    /// Commutating open loop at increasing speed while watching for zero crossings
+    Ramp,
+    /// Commutating 30° electrical after each zero crossing
+    Running,
+    /// An expected zero crossing never came, the motor has been left to coast
+    Lost,
+}
+
+pub enum Action {
+    /// Leave the phases as they are
+    None,
+    /// Drive the given commutation state
+    Commutate(CommutationState),
+    /// Float all phases
+    Coast,
+}
+
+pub struct Sensorless {
+    stage: Stage,
+    direction: bool,
+    state: CommutationState,
+    /// Periods since the last commutation
+    elapsed: u32,
+    /// Length of a step, 60° electrical
+    period: u32,
+    /// Periods since the last zero crossing, once one has been seen
+    since_crossing: Option<u32>,
+    /// Steps taken since leaving `Align`
+    steps: u32,
+    /// Consecutive steps in which a zero crossing was seen
+    crossings: u32,
+    crossed: bool,
+    /// Whether the floating phase was above the neutral point at the last sample
+    above: Option<bool>,
+    /// When to commutate, counted from the last commutation
+    commutate_at: Option<u32>,
+}
+
+impl Sensorless {
+    pub const fn new() -> Self {
+        Self {
+            stage: Stage::Stopped,
+            direction: false,
+            state: CommutationState::AB,
+            elapsed: 0,
+            period: RAMP_START_PERIOD,
+            since_crossing: None,
+            steps: 0,
+            crossings: 0,
+            crossed: false,
+            above: None,
+            commutate_at: None,
+        }
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    pub fn direction(&self) -> bool {
+        self.direction
+    }
+
+    /// Commutation state that should currently be driven
+    pub fn state(&self) -> CommutationState {
+        self.state
+    }
+
+    /// Phase whose back-EMF should be sampled, numbered 0 to 2 for A to C
+    pub fn floating(&self) -> usize {
+        self.state.phases().2
+    }
+
+    /// Begin aligning, returning the state to hold
+    ///
+    /// `direction` has the same meaning as in `MotorDriver::step`.
+    pub fn start(&mut self, direction: bool) -> CommutationState {
+        *self = Self::new();
+        self.stage = Stage::Align;
+        self.direction = direction;
+        self.state
+    }
+
+    pub fn stop(&mut self) {
+        self.stage = Stage::Stopped;
+    }
+
+    /// Advance by one PWM period given the floating phase and bus voltages, in the same units
+    pub fn update(&mut self, floating: u16, bus: u16) -> Action {
+        self.elapsed += 1;
+        self.since_crossing = self.since_crossing.map(|periods| periods + 1);
+
+        match self.stage {
+            Stage::Stopped | Stage::Lost => Action::None,
+            Stage::Align => {
+                if self.elapsed >= ALIGN_PERIODS {
+                    self.stage = Stage::Ramp;
+                    self.period = RAMP_START_PERIOD;
+                    self.commutate()
+                } else {
+                    Action::None
+                }
+            }
+            Stage::Ramp => {
+                if self.zero_crossing(floating, bus) {
+                    self.crossed = true;
+                    self.since_crossing = Some(0);
+                }
+
+                if self.elapsed < self.period {
+                    return Action::None;
+                }
+
+                self.crossings = if self.crossed { self.crossings + 1 } else { 0 };
+                self.steps += 1;
+
+                if self.crossings >= SYNC_CROSSINGS {
+                    self.stage = Stage::Running;
+                } else if self.steps >= RAMP_MAX_STEPS {
+                    return self.lose_sync();
+                } else {
+                    self.period = core::cmp::max(self.period - self.period / 16, RAMP_END_PERIOD);
+                }
+
+                self.commutate()
+            }
+            Stage::Running => {
+                if self.commutate_at.is_none() && self.zero_crossing(floating, bus) {
+                    // Timed between crossings, as the steps of the ramp need not have been
+                    // centred on them
+                    if let Some(periods) = self.since_crossing.replace(0) {
+                        self.period = periods;
+                    }
+                    self.commutate_at = Some(self.elapsed + self.period / 2);
+                }
+
+                match self.commutate_at {
+                    Some(at) if self.elapsed >= at => self.commutate(),
+                    None if self.elapsed > 2 * self.period => self.lose_sync(),
+                    _ => Action::None,
+                }
+            }
+        }
+    }
+
+    fn commutate(&mut self) -> Action {
+        self.state = self.state.advance(self.direction);
+        self.elapsed = 0;
+        self.crossed = false;
+        self.above = None;
+        self.commutate_at = None;
+
+        Action::Commutate(self.state)
+    }
+
+    fn lose_sync(&mut self) -> Action {
+        self.stage = Stage::Lost;
+        Action::Coast
+    }
+
+    /// Whether the floating phase just crossed half the bus voltage in the expected direction
+    fn zero_crossing(&mut self, floating: u16, bus: u16) -> bool {
+        if self.elapsed < core::cmp::max(self.period / 4, MIN_BLANKING) {
+            return false;
+        }
+
+        let above = floating > bus / 2;
+        let previous = self.above.replace(above);
+
+        // The floating phase rises if it was driven low in the previous state
+        let rising = self.state.advance(!self.direction).phases().1 == self.floating();
+
+        match previous {
+            Some(previous) => previous != above && above == rising,
+            None => false,
+        }
+    }
+}
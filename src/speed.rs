@@ -0,0 +1,69 @@
+//! Electrical speed from commutation timing
+//!
+//! Nothing here touches hardware, edges and ticks are fed in by the interrupt handlers.
+
+use crate::motor::CommutationState;
+
+/// Steps per electrical revolution
+const STEPS: u32 = 6;
+
+/// Speed in electrical RPM, measured from the time between commutation edges
+///
+/// Positive in the forward direction, which is `CommutationState::previous` order.
+pub struct Tachometer {
+    /// Rate at which `tick` is called
+    tick_hz: u32,
+    position: Option<CommutationState>,
+    /// 1.0 forward, -1.0 reverse, 0.0 when unknown
+    direction: f32,
+    /// Ticks since the last edge
+    elapsed: u32,
+    /// Ticks the previous step took, 0 when unknown
+    period: u32,
+}
+
+impl Tachometer {
+    pub const fn new(tick_hz: u32) -> Self {
+        Self {
+            tick_hz,
+            position: None,
+            direction: 0.0,
+            elapsed: 0,
+            period: 0,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.elapsed = self.elapsed.saturating_add(1);
+    }
+
+    /// Record the rotor, or the driven commutation state, moving to `position`
+    pub fn edge(&mut self, position: CommutationState) {
+        let direction = match self.position {
+            Some(previous) if position == previous.previous() => 1.0,
+            Some(previous) if position == previous.next() => -1.0,
+            _ => 0.0,
+        };
+
+        // Only trust the step time if the rotor kept going the same way through it
+        self.period = if direction != 0.0 && direction == self.direction {
+            self.elapsed
+        } else {
+            0
+        };
+        self.direction = direction;
+        self.elapsed = 0;
+        self.position = Some(position);
+    }
+
+    pub fn erpm(&self) -> f32 {
+        // A step taking longer than the last means the motor is slowing down, and one taking
+        // longer than half a second means it has stopped
+        let ticks = core::cmp::max(self.period, self.elapsed);
+        if self.period == 0 || ticks > self.tick_hz / 2 {
+            return 0.0;
+        }
+
+        self.direction * (60 * self.tick_hz) as f32 / (STEPS * ticks) as f32
+    }
+}
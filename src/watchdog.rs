@@ -0,0 +1,128 @@
+//! Independent watchdog, fed only while both idle and the motor task get to run
+
+use stm32f4xx_hal::stm32::{DBGMCU, IWDG, RCC};
+
+/// LSI divided by 64, so one count is 2ms
+const PRESCALER: u8 = 0b100;
+const MS_PER_COUNT: u32 = 2;
+const MAX_RELOAD: u32 = 0xfff;
+
+const KEY_UNLOCK: u16 = 0x5555;
+const KEY_FEED: u16 = 0xaaaa;
+const KEY_START: u16 = 0xcccc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetCause {
+    PowerOn,
+    Brownout,
+    Pin,
+    Software,
+    Watchdog,
+    WindowWatchdog,
+    LowPower,
+}
+
+impl ResetCause {
+    pub fn description(&self) -> &'static str {
+        match self {
+            ResetCause::PowerOn => "power on reset",
+            ResetCause::Brownout => "brownout reset",
+            ResetCause::Pin => "reset pin",
+            ResetCause::Software => "software reset",
+            ResetCause::Watchdog => "watchdog reset",
+            ResetCause::WindowWatchdog => "window watchdog reset",
+            ResetCause::LowPower => "low power reset",
+        }
+    }
+}
+
+/// Why the chip last reset, clearing the flags so the next reset reads fresh
+pub fn reset_cause() -> ResetCause {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    let csr = rcc.csr.read();
+
+    // Several flags can be set at once, so check the most specific first
+    let cause = if csr.iwdgrstf().bit_is_set() {
+        ResetCause::Watchdog
+    } else if csr.wwdgrstf().bit_is_set() {
+        ResetCause::WindowWatchdog
+    } else if csr.lpwrrstf().bit_is_set() {
+        ResetCause::LowPower
+    } else if csr.sftrstf().bit_is_set() {
+        ResetCause::Software
+    } else if csr.porrstf().bit_is_set() {
+        ResetCause::PowerOn
+    } else if csr.borrstf().bit_is_set() {
+        ResetCause::Brownout
+    } else {
+        ResetCause::Pin
+    };
+
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+
+    cause
+}
+
+pub struct Watchdog {
+    iwdg: IWDG,
+    idle: bool,
+    motor: bool,
+}
+
+impl Watchdog {
+    /// Start the watchdog, which cannot be stopped again until reset
+    ///
+    /// It is frozen while the core is halted by a debugger.
+    pub fn start(iwdg: IWDG, dbgmcu: &DBGMCU, timeout_ms: u32) -> Self {
+        dbgmcu.apb1_fz.modify(|_, w| w.dbg_iwdg_stop().set_bit());
+
+        iwdg.kr.write(|w| unsafe { w.key().bits(KEY_START) });
+
+        let mut watchdog = Self {
+            iwdg,
+            idle: false,
+            motor: false,
+        };
+        watchdog.set_timeout(timeout_ms);
+        watchdog
+    }
+
+    /// Change the time allowed between feeds, up to about 8 seconds, and feed
+    pub fn set_timeout(&mut self, timeout_ms: u32) {
+        let reload = core::cmp::min(timeout_ms / MS_PER_COUNT, MAX_RELOAD);
+
+        self.iwdg.kr.write(|w| unsafe { w.key().bits(KEY_UNLOCK) });
+        self.iwdg.pr.write(|w| unsafe { w.pr().bits(PRESCALER) });
+        self.iwdg
+            .rlr
+            .write(|w| unsafe { w.rl().bits(reload as u16) });
+        while self.iwdg.sr.read().bits() != 0 {}
+
+        self.feed();
+    }
+
+    /// Record that idle has run, which it only does while no task is stuck running
+    pub fn idle_alive(&mut self) {
+        self.idle = true;
+        self.feed_if_alive();
+    }
+
+    /// Record that the motor task has run
+    pub fn motor_alive(&mut self) {
+        self.motor = true;
+        self.feed_if_alive();
+    }
+
+    /// Feed once both have checked in since the last feed
+    fn feed_if_alive(&mut self) {
+        if self.idle && self.motor {
+            self.idle = false;
+            self.motor = false;
+            self.feed();
+        }
+    }
+
+    fn feed(&mut self) {
+        self.iwdg.kr.write(|w| unsafe { w.key().bits(KEY_FEED) });
+    }
+}